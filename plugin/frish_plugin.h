@@ -0,0 +1,31 @@
+/* Interface of frish plugins, shared objects loaded by `enable -f FILE`.
+ *
+ * A plugin exports the function frish_plugin_init returning the description
+ * of its builtins. The description, the names and the hints must stay valid
+ * while the shell runs, the plugin is never unloaded. A handler gets the
+ * arguments with the command name first, terminated by a null pointer, and
+ * returns the status of the command.
+ */
+#ifndef FRISH_PLUGIN_H
+#define FRISH_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define FRISH_PLUGIN_ABI_VERSION 1
+
+struct frish_builtin {
+    const char *name;
+    const char *hint;
+    int (*handler)(int argc, const char *const *argv);
+};
+
+struct frish_plugin {
+    uint32_t abi_version; /* FRISH_PLUGIN_ABI_VERSION */
+    size_t count;
+    const struct frish_builtin *builtins;
+};
+
+const struct frish_plugin *frish_plugin_init(void);
+
+#endif
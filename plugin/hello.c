@@ -0,0 +1,35 @@
+/* Example plugin: cc -shared -fPIC -o hello.so hello.c, then enable -f ./hello.so */
+#include <stdio.h>
+
+#include "frish_plugin.h"
+
+static int do_hello(int argc, const char *const *argv)
+{
+    printf("Hello");
+    for (int i = 1; i < argc; i++)
+        printf(" %s", argv[i]);
+    printf("!\n");
+    return 0;
+}
+
+static int do_fail(int argc, const char *const *argv)
+{
+    (void)argv;
+    return argc;
+}
+
+static const struct frish_builtin builtins[] = {
+    {"hello", "Greet the arguments", do_hello},
+    {"fail", "Fail with the number of arguments", do_fail},
+};
+
+static const struct frish_plugin plugin = {
+    FRISH_PLUGIN_ABI_VERSION,
+    sizeof(builtins) / sizeof(builtins[0]),
+    builtins,
+};
+
+const struct frish_plugin *frish_plugin_init(void)
+{
+    return &plugin;
+}
@@ -1,15 +1,119 @@
-use std::io; // Result
+use std::io::{self, Write};
 use std::str::FromStr;
 
+use crate::builtins::opts::{usage_error, Opt, Spec, MANY};
+use crate::builtins::plugin;
+use crate::editor::Completer;
+use crate::parser::quote;
+use crate::shell::options::{self, ShellOption};
 use crate::state::{State, Status};
 
 pub fn do_help(state: &State, _args: &[&str]) -> io::Result<Status> {
-    for b in &state.builtins.items {
+    for b in state.builtins.borrow().iter() {
         println!("{:16}{}", b.command, b.hint);
     }
     Ok(Status::success())
 }
 
+const ENABLE: Spec = Spec::new(
+    "[NAME]...",
+    0,
+    MANY,
+    &[
+        Opt::flag(
+            'n',
+            "disable",
+            "Disable the builtins, or print the disabled ones",
+        ),
+        Opt::flag('a', "all", "Print all builtins, enabled or not"),
+        Opt::value(
+            'f',
+            "file",
+            "FILE",
+            "Load the builtins (all or the named ones) from the plugin",
+        ),
+        Opt::flag('d', "delete", "Remove the builtins"),
+    ],
+);
+
+// enable or disable the named builtins
+fn set_enabled(state: &State, names: &[&str], enabled: bool) -> Status {
+    let mut builtins = state.builtins.borrow_mut();
+    let mut status = Status::success();
+    for name in names {
+        if !builtins.set_enabled(name, enabled) {
+            eprintln!("Error: {}: No such builtin", name);
+            status = Status::from_code(1);
+        }
+    }
+    status
+}
+
+fn load_plugin(state: &State, file: &str, names: &[&str]) -> Status {
+    let loaded = match plugin::load(file) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return Status::from_code(1);
+        }
+    };
+    let mut status = Status::success();
+    for name in names {
+        if !loaded.iter().any(|builtin| builtin.command == *name) {
+            eprintln!("Error: {}: No such builtin in {}", name, file);
+            status = Status::from_code(1);
+        }
+    }
+    let mut builtins = state.builtins.borrow_mut();
+    for builtin in loaded {
+        if names.is_empty() || names.contains(&builtin.command) {
+            builtins.register(builtin);
+        }
+    }
+    status
+}
+
+pub fn do_enable(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, ENABLE, args);
+    if let Some(file) = opts.value("file") {
+        return Ok(load_plugin(state, file, &opts.args));
+    }
+    if opts.has("delete") {
+        let mut builtins = state.builtins.borrow_mut();
+        let mut status = Status::success();
+        for name in &opts.args {
+            if builtins.unregister(name).is_none() {
+                eprintln!("Error: {}: No such builtin", name);
+                status = Status::from_code(1);
+            }
+        }
+        return Ok(status);
+    }
+    if !opts.args.is_empty() {
+        return Ok(set_enabled(state, &opts.args, !opts.has("disable")));
+    }
+    // print the builtins in the form of commands
+    for (builtin, enabled) in state.builtins.borrow().all() {
+        match enabled {
+            true if opts.has("all") || !opts.has("disable") => {
+                println!("enable {}", builtin.command)
+            }
+            false if opts.has("all") || opts.has("disable") => {
+                println!("enable -n {}", builtin.command)
+            }
+            _ => {}
+        }
+    }
+    Ok(Status::success())
+}
+
+const DISABLE: Spec = Spec::new("NAME...", 1, MANY, &[]);
+
+pub fn do_disable(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DISABLE, args);
+    Ok(set_enabled(state, &opts.args, false))
+}
+
 pub fn do_name(state: &State, args: &[&str]) -> io::Result<Status> {
     if args.len() > 1 {
         state.set_name(args[1])
@@ -29,20 +133,242 @@ pub fn do_loglevel(_: &State, args: &[&str]) -> io::Result<Status> {
     Ok(Status::success())
 }
 
-pub fn do_print(_: &State, args: &[&str]) -> io::Result<Status> {
-    let last = args.last().unwrap();
-    for arg in args.iter().skip(1) {
-        print!("{}", arg);
-        if arg != last {
-            print!(" ");
+// the options as `set -o` prints them, or as the commands setting them
+fn print_options(state: &State, commands: bool) {
+    for opt in ShellOption::ALL {
+        match (commands, state.option(opt)) {
+            (false, on) => println!("{:15}{}", opt.name(), if on { "on" } else { "off" }),
+            (true, true) => println!("set -o {}", opt.name()),
+            (true, false) => println!("set +o {}", opt.name()),
+        }
+    }
+}
+
+pub fn do_set(state: &State, args: &[&str]) -> io::Result<Status> {
+    match args[1..] {
+        [] | ["-o"] => {
+            print_options(state, false);
+            return Ok(Status::success());
+        }
+        ["+o"] => {
+            print_options(state, true);
+            return Ok(Status::success());
+        }
+        ["--help"] => {
+            println!("Usage: set [-eunxC] [+eunxC] [-o OPTION] [+o OPTION]");
+            println!("Turn the options on by -, off by +; without arguments print them\n");
+            for opt in ShellOption::ALL {
+                let letter = opt.letter().map(|ch| format!("-{}", ch));
+                println!("  {:4}{}", letter.unwrap_or_default(), opt.name());
+            }
+            return Ok(Status::success());
+        }
+        _ => {}
+    }
+    let mut i = 1;
+    while i < args.len() {
+        if !options::is_option_word(args[i]) {
+            return Ok(usage_error(
+                args[0],
+                &format!("extra operand '{}'", args[i]),
+            ));
+        }
+        let (settings, used) = match options::parse_word(&args[i..]) {
+            Ok(parsed) => parsed,
+            Err(msg) => return Ok(usage_error(args[0], &msg)),
         };
+        for (opt, on) in settings {
+            state.set_option(opt, on);
+        }
+        i += used;
     }
     Ok(Status::success())
 }
 
-pub fn do_echo(state: &State, args: &[&str]) -> io::Result<Status> {
-    do_print(state, args).and_then(|_| {
-        println!("");
-        Ok(Status::success())
-    })
+const HISTORY: Spec = Spec::new(
+    "[N]",
+    0,
+    1,
+    &[
+        Opt::flag('c', "clear", "Clear the history"),
+        Opt::value('d', "delete", "NUMBER", "Delete the entry with the number"),
+    ],
+);
+
+pub fn do_history(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, HISTORY, args);
+    let mut history = state.history.borrow_mut();
+    if opts.has("clear") {
+        history.clear()?;
+        return Ok(Status::success());
+    }
+    match opts.number::<usize>("delete") {
+        Ok(Some(number)) if history.delete(number)? => return Ok(Status::success()),
+        Ok(Some(number)) => {
+            eprintln!("Error: {}: No such history entry", number);
+            return Ok(Status::from_code(1));
+        }
+        Ok(None) => {}
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    }
+    // the last N entries
+    let count = match opts.args.first().map(|count| count.parse::<usize>()) {
+        None => usize::MAX,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            let msg = format!("invalid number '{}'", opts.args[0]);
+            return Ok(usage_error(args[0], &msg));
+        }
+    };
+    let entries: Vec<_> = history.numbered().collect();
+    for (number, entry) in &entries[entries.len().saturating_sub(count)..] {
+        println!("{:5}  {}", number, entry);
+    }
+    Ok(Status::success())
+}
+
+const COMPLETE: Spec = Spec::new(
+    "[NAME]...",
+    0,
+    MANY,
+    &[
+        Opt::value(
+            'W',
+            "words",
+            "WORDS",
+            "Complete the arguments from the words",
+        ),
+        Opt::value(
+            'C',
+            "command",
+            "COMMAND",
+            "Complete the arguments from the command output",
+        ),
+        Opt::flag('r', "remove", "Remove the completers of the commands"),
+    ],
+);
+
+pub fn do_complete(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, COMPLETE, args);
+    let mut completers = state.completers.borrow_mut();
+    let completer = match (opts.value("words"), opts.value("command")) {
+        (Some(_), Some(_)) => {
+            return Ok(usage_error(args[0], "options -W and -C exclude each other"))
+        }
+        (Some(words), None) => Some(Completer::Words(
+            words.split_whitespace().map(String::from).collect(),
+        )),
+        (None, Some(command)) => Some(Completer::Command(command.to_string())),
+        (None, None) => None,
+    };
+    if opts.has("remove") {
+        for name in &opts.args {
+            completers.remove(*name);
+        }
+        return Ok(Status::success());
+    }
+    match completer {
+        Some(completer) if !opts.args.is_empty() => {
+            for name in &opts.args {
+                completers.insert(name.to_string(), completer.clone());
+            }
+        }
+        Some(_) => return Ok(usage_error(args[0], "missing command name")),
+        None => {
+            // print the completers in the form of commands
+            let mut names: Vec<_> = completers.keys().collect();
+            names.sort();
+            for name in names {
+                if !opts.args.is_empty() && !opts.args.contains(&name.as_str()) {
+                    continue;
+                }
+                match &completers[name] {
+                    Completer::Words(words) => {
+                        println!("complete -W {} {}", quote(&words.join(" ")), name)
+                    }
+                    Completer::Command(command) => {
+                        println!("complete -C {} {}", quote(command), name)
+                    }
+                }
+            }
+        }
+    }
+    Ok(Status::success())
+}
+
+pub fn do_print(_: &State, args: &[&str]) -> io::Result<Status> {
+    print!("{}", args[1..].join(" "));
+    Ok(Status::success())
+}
+
+// interpret backslash escapes, the flag tells whether to stop the output (\c);
+// the numeric escapes are bytes, e.g. `\xe2\x82\xac` is the euro sign in UTF-8
+fn unescape(arg: &str) -> (Vec<u8>, bool) {
+    let mut res = Vec::new();
+    let mut iter = arg.bytes().peekable();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            res.push(byte);
+            continue;
+        }
+        match iter.next() {
+            Some(b'a') => res.push(b'\x07'),
+            Some(b'b') => res.push(b'\x08'),
+            Some(b'c') => return (res, true),
+            Some(b'e') => res.push(b'\x1b'),
+            Some(b'f') => res.push(b'\x0c'),
+            Some(b'n') => res.push(b'\n'),
+            Some(b'r') => res.push(b'\r'),
+            Some(b't') => res.push(b'\t'),
+            Some(b'v') => res.push(b'\x0b'),
+            Some(b'\\') => res.push(b'\\'),
+            Some(base @ (b'0' | b'x')) => {
+                let (radix, len) = if base == b'0' { (8, 3) } else { (16, 2) };
+                let mut code = 0;
+                for _ in 0..len {
+                    match iter.peek().and_then(|&b| char::from(b).to_digit(radix)) {
+                        Some(digit) => code = code * radix + digit,
+                        None => break,
+                    }
+                    iter.next();
+                }
+                res.push(code as u8);
+            }
+            Some(byte) => res.extend_from_slice(&[b'\\', byte]),
+            None => res.push(b'\\'),
+        }
+    }
+    (res, false)
+}
+
+// the leading words made only of the options n and e, e.g. `-n` or `-ne`,
+// as other shells all other words are printed, even `--help` and `--`
+fn echo_options<'a, 'b>(args: &'b [&'a str]) -> (bool, bool, &'b [&'a str]) {
+    let (mut newline, mut escapes) = (true, false);
+    let mut i = 1;
+    while let Some(letters) = args.get(i).and_then(|arg| arg.strip_prefix('-')) {
+        if letters.is_empty() || !letters.chars().all(|ch| ch == 'n' || ch == 'e') {
+            break;
+        }
+        newline &= !letters.contains('n');
+        escapes |= letters.contains('e');
+        i += 1;
+    }
+    (newline, escapes, &args[i..])
+}
+
+pub fn do_echo(_: &State, args: &[&str]) -> io::Result<Status> {
+    let (mut newline, escapes, words) = echo_options(args);
+    let line = words.join(" ");
+    let (mut line, stop) = if escapes {
+        unescape(&line)
+    } else {
+        (line.into_bytes(), false)
+    };
+    newline &= !stop;
+    if newline {
+        line.push(b'\n');
+    }
+    io::stdout().write_all(&line)?;
+    Ok(Status::success())
 }
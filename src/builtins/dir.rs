@@ -1,27 +1,272 @@
-use nix::{sys::stat, unistd, NixPath};
-use std::fs; // portable FS functions, TODO: use only nix::*
-use std::io;
+use nix::{
+    dir::Dir,
+    errno::Errno,
+    fcntl::{self, AtFlags, OFlag},
+    sys::stat,
+    unistd::{self, UnlinkatFlags},
+    NixPath,
+};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr, OsString};
+use std::io::{self, Write};
+use std::os::unix::{
+    ffi::{OsStrExt, OsStringExt},
+    io::{AsRawFd, RawFd},
+};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::common::report_nixerror;
+use crate::builtins::file::{copy_fd, temp_template};
+use crate::builtins::opts::{usage_error, Opt, Opts, Spec, MANY};
+use crate::common::{
+    format_time, isatty, report_error, report_nixerror, terminal_width, Command, DIR_MODE,
+};
+use crate::shell::exec::run_command;
 use crate::state::{State, Status};
 
-pub fn do_dir_change(_: &State, args: &[&str]) -> io::Result<Status> {
-    let path = if args.len() == 1 { "/" } else { args[1] };
-    unistd::chdir(path)?;
+// ********** current directory **********
+
+// lexically resolve '.' and '..' components of an absolute path
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::from("/");
+    for comp in path.components() {
+        match comp {
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::Normal(name) => res.push(name),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    res
+}
+
+// change the directory and update PWD and OLDPWD,
+// the logical path keeps symlinks unless physical is requested
+fn change_dir(state: &State, path: &str, physical: bool) -> io::Result<()> {
+    let oldpwd = match state.get_var("PWD") {
+        Some(pwd) => pwd,
+        None => unistd::getcwd()?.to_string_lossy().into_owned(),
+    };
+    let logical = normalize(&Path::new(&oldpwd).join(path));
+    let pwd = if !physical && unistd::chdir(&logical).is_ok() {
+        logical
+    } else {
+        unistd::chdir(path)?;
+        unistd::getcwd()?
+    };
+    state.set_var("OLDPWD", &oldpwd);
+    state.set_var("PWD", &pwd.to_string_lossy());
+    Ok(())
+}
+
+// find a relative directory in CDPATH
+fn search_cdpath(state: &State, path: &str) -> Option<String> {
+    if path.starts_with('/') || path == "." || path == ".." {
+        return None;
+    }
+    if path.starts_with("./") || path.starts_with("../") {
+        return None;
+    }
+    let cdpath = state.get_var("CDPATH")?;
+    cdpath
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(path))
+        .find(|candidate| candidate.is_dir())
+        .map(|candidate| candidate.to_string_lossy().into_owned())
+}
+
+const DIR_CHANGE: Spec = Spec::new(
+    "[DIR|-]",
+    0,
+    1,
+    &[Opt::flag(
+        'P',
+        "physical",
+        "Resolve symbolic links in the new path",
+    )],
+);
+
+pub fn do_dir_change(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_CHANGE, args);
+    let (path, show) = match opts.args.first() {
+        None => match state.get_var("HOME") {
+            Some(home) => (home, false),
+            None => {
+                eprintln!("{}: HOME not set", args[0]);
+                return Ok(Status::from_code(1));
+            }
+        },
+        Some(&"-") => match state.get_var("OLDPWD") {
+            Some(oldpwd) => (oldpwd, true),
+            None => {
+                eprintln!("{}: OLDPWD not set", args[0]);
+                return Ok(Status::from_code(1));
+            }
+        },
+        Some(&path) => match search_cdpath(state, path) {
+            Some(found) => (found, true),
+            None => (String::from(path), false),
+        },
+    };
+    change_dir(state, &path, opts.has("physical"))?;
+    if show {
+        println!("{}", state.get_var("PWD").unwrap_or_default());
+    }
     Ok(Status::success())
 }
 
-pub fn do_dir_where(_: &State, _args: &[&str]) -> io::Result<Status> {
-    let path = unistd::getcwd()?;
-    println!("{}", path.display());
+const DIR_WHERE: Spec = Spec::new(
+    "",
+    0,
+    0,
+    &[Opt::flag(
+        'P',
+        "physical",
+        "Print the path without symbolic links",
+    )],
+);
+
+pub fn do_dir_where(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_WHERE, args);
+    match state.get_var("PWD") {
+        Some(pwd) if !opts.has("physical") => println!("{}", pwd),
+        _ => println!("{}", unistd::getcwd()?.display()),
+    }
+    Ok(Status::success())
+}
+
+// ********** directory stack **********
+
+// the current directory followed by the stack, top first
+fn print_stack(state: &State, verbose: bool) {
+    let pwd = state.get_var("PWD").unwrap_or_default();
+    let stack = state.dirstack.borrow();
+    let dirs = std::iter::once(&pwd).chain(stack.iter().rev());
+    let dirs: Vec<String> = dirs.map(|dir| state.tilde_dir(dir)).collect();
+    if verbose {
+        for (i, dir) in dirs.iter().enumerate() {
+            println!("{:2}  {}", i, dir);
+        }
+    } else {
+        println!("{}", dirs.join(" "));
+    }
+}
+
+const DIR_PUSH: Spec = Spec::new("[DIR]", 0, 1, &[]);
+
+pub fn do_dir_push(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_PUSH, args);
+    let pwd = state.get_var("PWD").unwrap_or_default();
+    let target = match opts.args.first() {
+        Some(&path) => String::from(path),
+        // swap the two top directories
+        None => match state.dirstack.borrow_mut().pop() {
+            Some(top) => top,
+            None => {
+                eprintln!("{}: no other directory", args[0]);
+                return Ok(Status::from_code(1));
+            }
+        },
+    };
+    if let Err(err) = change_dir(state, &target, false) {
+        if opts.args.is_empty() {
+            state.dirstack.borrow_mut().push(target);
+        }
+        return Err(err);
+    }
+    state.dirstack.borrow_mut().push(pwd);
+    print_stack(state, false);
+    Ok(Status::success())
+}
+
+const DIR_POP: Spec = Spec::new("", 0, 0, &[]);
+
+pub fn do_dir_pop(state: &State, args: &[&str]) -> io::Result<Status> {
+    parse_opts!(state, DIR_POP, args);
+    let top = state.dirstack.borrow_mut().pop();
+    match top {
+        Some(top) => {
+            if let Err(err) = change_dir(state, &top, false) {
+                state.dirstack.borrow_mut().push(top);
+                return Err(err);
+            }
+            print_stack(state, false);
+            Ok(Status::success())
+        }
+        None => {
+            eprintln!("{}: directory stack empty", args[0]);
+            Ok(Status::from_code(1))
+        }
+    }
+}
+
+const DIR_STACK: Spec = Spec::new(
+    "",
+    0,
+    0,
+    &[
+        Opt::flag('c', "clear", "Clear the directory stack"),
+        Opt::flag(
+            'v',
+            "verbose",
+            "Print one directory per line with its position",
+        ),
+    ],
+);
+
+pub fn do_dir_stack(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_STACK, args);
+    if opts.has("clear") {
+        state.dirstack.borrow_mut().clear();
+    } else {
+        print_stack(state, opts.has("verbose"));
+    }
     Ok(Status::success())
 }
 
-pub fn do_dir_make(_: &State, args: &[&str]) -> io::Result<Status> {
+const DIR_MAKE: Spec = Spec::new(
+    "DIR...",
+    1,
+    MANY,
+    &[Opt::flag(
+        'p',
+        "parents",
+        "Make parent directories as needed, no error if existing",
+    )],
+);
+
+// make the directory and its missing ancestors
+fn mkdir_parents(path: &Path, mode: stat::Mode) -> nix::Result<()> {
+    match unistd::mkdir(path, mode) {
+        Err(Errno::ENOENT) => {
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                mkdir_parents(parent, mode)?;
+            }
+            unistd::mkdir(path, mode)
+        }
+        Err(Errno::EEXIST) if path.is_dir() => Ok(()),
+        res => res,
+    }
+}
+
+pub fn do_dir_make(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_MAKE, args);
     let mut status = 0;
-    for arg in &args[1..] {
-        let path = std::path::PathBuf::from(arg);
-        if let Err(err) = unistd::mkdir(&path, stat::Mode::S_IRWXU) {
+    for arg in &opts.args {
+        let path = Path::new(arg);
+        let res = if opts.has("parents") {
+            mkdir_parents(path, DIR_MODE)
+        } else {
+            unistd::mkdir(path, DIR_MODE)
+        };
+        if let Err(err) = res {
             report_nixerror(&err);
             status = nix::errno::errno();
         }
@@ -36,11 +281,56 @@ fn rmdir<P: ?Sized + NixPath>(path: &P) -> nix::Result<()> {
     nix::errno::Errno::result(res).map(drop)
 }
 
-pub fn do_dir_remove(_: &State, args: &[&str]) -> io::Result<Status> {
+// remove the directory and its contents, symlinks are removed, never followed,
+// errors are reported and the removal continues with the next entry
+fn remove_tree(dirfd: RawFd, name: &OsStr) -> nix::Result<()> {
+    let report = |err| {
+        eprintln!("Error: {}: {}", name.to_string_lossy(), err);
+        err
+    };
+    let mut dir = open_dir_at(dirfd, name).map_err(report)?;
+    let subfd = dir.as_raw_fd();
+    let mut res = Ok(());
+    for entry in read_dir_entries(&mut dir).map_err(report)? {
+        if entry.is_dot() {
+            continue;
+        }
+        let entry_res = if entry.is_dir() {
+            remove_tree(subfd, &entry.name)
+        } else {
+            let name = entry.name.as_os_str();
+            unistd::unlinkat(Some(subfd), name, UnlinkatFlags::NoRemoveDir).inspect_err(|err| {
+                eprintln!("Error: {}: {}", entry.display_name(), err);
+            })
+        };
+        res = res.and(entry_res);
+    }
+    drop(dir);
+    res?;
+    unistd::unlinkat(Some(dirfd), name, UnlinkatFlags::RemoveDir).map_err(report)
+}
+
+const DIR_REMOVE: Spec = Spec::new(
+    "DIR...",
+    1,
+    MANY,
+    &[Opt::flag(
+        'r',
+        "recursive",
+        "Remove directories and their contents",
+    )],
+);
+
+pub fn do_dir_remove(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_REMOVE, args);
     let mut status = 0;
-    for arg in &args[1..] {
-        let path = std::path::PathBuf::from(arg);
-        if let Err(err) = rmdir(&path) {
+    for arg in &opts.args {
+        let path = Path::new(arg);
+        if opts.has("recursive") {
+            if let Err(err) = remove_tree(libc::AT_FDCWD, path.as_os_str()) {
+                status = err as i32;
+            }
+        } else if let Err(err) = rmdir(path) {
             report_nixerror(&err);
             status = nix::errno::errno();
         }
@@ -48,38 +338,1019 @@ pub fn do_dir_remove(_: &State, args: &[&str]) -> io::Result<Status> {
     Ok(Status::from_code(status))
 }
 
-fn get_dir_entries(args: &[&str]) -> std::io::Result<fs::ReadDir> {
-    let path = if args.len() > 1 {
-        std::path::PathBuf::from(args[1])
+// ********** directory entries **********
+
+struct Entry {
+    name: OsString,
+    stat: stat::FileStat,
+    target: Option<OsString>,
+}
+
+impl Entry {
+    fn kind(&self) -> stat::SFlag {
+        file_kind(self.stat.st_mode)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind() == stat::SFlag::S_IFDIR
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.name.as_bytes().starts_with(b".")
+    }
+
+    fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+
+    fn display_name(&self) -> Cow<'_, str> {
+        self.name.to_string_lossy()
+    }
+}
+
+// open a directory relative to dirfd, a symlink is never followed
+fn open_dir_at(dirfd: RawFd, name: &OsStr) -> nix::Result<Dir> {
+    let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    Dir::openat(dirfd, name, flags, stat::Mode::empty())
+}
+
+// read entries of a directory, '.' and '..' included, without following symlinks
+fn read_entries(path: &Path) -> nix::Result<Vec<Entry>> {
+    let mut dir = Dir::open(
+        path,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+        stat::Mode::empty(),
+    )?;
+    read_dir_entries(&mut dir)
+}
+
+fn read_dir_entries(dir: &mut Dir) -> nix::Result<Vec<Entry>> {
+    let dirfd = dir.as_raw_fd();
+    let mut entries = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        match stat::fstatat(dirfd, name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+            Ok(stat) => {
+                let is_link = file_kind(stat.st_mode) == stat::SFlag::S_IFLNK;
+                entries.push(Entry {
+                    name: OsStr::from_bytes(name.to_bytes()).to_os_string(),
+                    stat,
+                    target: if is_link {
+                        fcntl::readlinkat(dirfd, name).ok()
+                    } else {
+                        None
+                    },
+                })
+            }
+            Err(err) => report_nixerror(&err),
+        }
+    }
+    Ok(entries)
+}
+
+/// File type bits of the mode.
+pub fn file_kind(mode: libc::mode_t) -> stat::SFlag {
+    stat::SFlag::from_bits_truncate(mode & stat::SFlag::S_IFMT.bits())
+}
+
+// one letter file type as in ls -l
+fn kind_char(kind: stat::SFlag) -> char {
+    match kind {
+        stat::SFlag::S_IFDIR => 'd',
+        stat::SFlag::S_IFLNK => 'l',
+        stat::SFlag::S_IFCHR => 'c',
+        stat::SFlag::S_IFBLK => 'b',
+        stat::SFlag::S_IFIFO => 'p',
+        stat::SFlag::S_IFSOCK => 's',
+        _ => '-',
+    }
+}
+
+/// File type and permission bits, e.g. drwxr-xr-x.
+pub fn mode_string(mode: libc::mode_t) -> String {
+    let mut res = String::with_capacity(10);
+    res.push(kind_char(file_kind(mode)));
+    let special = [(0o4000, 's'), (0o2000, 's'), (0o1000, 't')];
+    for (i, &(bit, ch)) in special.iter().enumerate() {
+        let shift = 6 - 3 * i;
+        res.push(if mode & (0o4 << shift) != 0 { 'r' } else { '-' });
+        res.push(if mode & (0o2 << shift) != 0 { 'w' } else { '-' });
+        res.push(match (mode & (0o1 << shift) != 0, mode & bit != 0) {
+            (true, true) => ch,
+            (false, true) => ch.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    res
+}
+
+// ANSI color of the entry as used by ls
+fn color(entry: &Entry) -> Option<&'static str> {
+    match entry.kind() {
+        stat::SFlag::S_IFDIR => Some("01;34"),
+        stat::SFlag::S_IFLNK => Some("01;36"),
+        stat::SFlag::S_IFIFO => Some("33"),
+        stat::SFlag::S_IFSOCK => Some("01;35"),
+        stat::SFlag::S_IFCHR | stat::SFlag::S_IFBLK => Some("01;33"),
+        _ if entry.stat.st_mode & 0o111 != 0 => Some("01;32"),
+        _ => None,
+    }
+}
+
+fn colored_name(entry: &Entry, colors: bool) -> String {
+    match color(entry).filter(|_| colors) {
+        Some(color) => format!("\x1b[{}m{}\x1b[0m", color, entry.display_name()),
+        None => entry.display_name().into_owned(),
+    }
+}
+
+/// Owner and group names, each id is resolved only once.
+#[derive(Default)]
+pub struct Names {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Names {
+    pub fn user(&mut self, uid: u32) -> &str {
+        self.users.entry(uid).or_insert_with(|| {
+            match unistd::User::from_uid(unistd::Uid::from_raw(uid)) {
+                Ok(Some(user)) => user.name,
+                _ => uid.to_string(),
+            }
+        })
+    }
+
+    pub fn group(&mut self, gid: u32) -> &str {
+        self.groups.entry(gid).or_insert_with(|| {
+            match unistd::Group::from_gid(unistd::Gid::from_raw(gid)) {
+                Ok(Some(group)) => group.name,
+                _ => gid.to_string(),
+            }
+        })
+    }
+}
+
+// size with a unit suffix, e.g. 4.0K or 12M
+fn human_size(size: i64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{:.1}{}", value, UNITS[unit])
     } else {
-        unistd::getcwd()?
+        format!("{:.0}{}", value, UNITS[unit])
+    }
+}
+
+// size column, devices show their major and minor numbers instead
+fn size_string(entry: &Entry, human: bool) -> String {
+    match entry.kind() {
+        stat::SFlag::S_IFCHR | stat::SFlag::S_IFBLK => format!(
+            "{}, {}",
+            stat::major(entry.stat.st_rdev),
+            stat::minor(entry.stat.st_rdev)
+        ),
+        _ if human => human_size(entry.stat.st_size),
+        _ => entry.stat.st_size.to_string(),
+    }
+}
+
+// long listing as ls -l: mode, links, owner, group, size, mtime, name and link target
+fn print_long(entries: &[Entry], colors: bool, human: bool) {
+    let mut names = Names::default();
+    let rows: Vec<[String; 5]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                mode_string(entry.stat.st_mode),
+                entry.stat.st_nlink.to_string(),
+                names.user(entry.stat.st_uid).to_string(),
+                names.group(entry.stat.st_gid).to_string(),
+                size_string(entry, human),
+            ]
+        })
+        .collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    for (entry, row) in entries.iter().zip(&rows) {
+        let mut line = format!(
+            "{} {:>w1$} {:w2$} {:w3$} {:>w4$} {} {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            format_time(entry.stat.st_mtime, "%b %e %H:%M"),
+            colored_name(entry, colors),
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        );
+        if let Some(target) = &entry.target {
+            line.push_str(" -> ");
+            line.push_str(&target.to_string_lossy());
+        }
+        println!("{}", line);
+    }
+}
+
+// ********** dir.list **********
+
+const DIR_LIST: Spec = Spec::new(
+    "[DIR]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('a', "all", "Do not hide entries starting with ."),
+        Opt::flag('l', "long", "Use a long listing format"),
+        Opt::flag('h', "human", "Print sizes in human readable format"),
+        Opt::flag('R', "recursive", "List subdirectories recursively"),
+        Opt::flag('S', "size", "Sort by file size, largest first"),
+        Opt::flag('t', "time", "Sort by modification time, newest first"),
+        Opt::flag('r', "reverse", "Reverse the order of sorting"),
+    ],
+);
+
+fn sort_entries(entries: &mut [Entry], opts: &Opts) {
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    if opts.has("size") {
+        entries.sort_by_key(|entry| Reverse(entry.stat.st_size));
+    } else if opts.has("time") {
+        entries.sort_by_key(|entry| Reverse((entry.stat.st_mtime, entry.stat.st_mtime_nsec)));
+    }
+    if opts.has("reverse") {
+        entries.reverse();
+    }
+}
+
+// names in columns (ordered down, then across) fitting the terminal width
+fn print_columns(entries: &[Entry], colors: bool, width: usize) {
+    if entries.is_empty() {
+        return;
+    }
+    let lens: Vec<usize> = entries
+        .iter()
+        .map(|entry| entry.display_name().chars().count())
+        .collect();
+    let mut rows = 1;
+    let widths = loop {
+        let widths: Vec<usize> = lens
+            .chunks(rows)
+            .map(|col| col.iter().max().unwrap() + 2)
+            .collect();
+        if rows >= lens.len() || widths.iter().sum::<usize>() <= width {
+            break widths;
+        }
+        rows += 1;
+    };
+    for row in 0..rows {
+        let mut line = String::new();
+        for (col, colwidth) in widths.iter().enumerate() {
+            let idx = col * rows + row;
+            if idx >= entries.len() {
+                break;
+            }
+            line.push_str(&colored_name(&entries[idx], colors));
+            if idx + rows < entries.len() {
+                line.push_str(&" ".repeat(colwidth - lens[idx]));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+fn list_dir(path: &Path, opts: &Opts, long: bool, header: bool) -> nix::Result<()> {
+    let mut entries = read_entries(path)?;
+    entries.retain(|entry| opts.has("all") || !entry.is_hidden());
+    sort_entries(&mut entries, opts);
+    if header {
+        println!("{}:", path.display());
+    }
+    let tty = isatty(libc::STDOUT_FILENO);
+    if long {
+        print_long(&entries, tty, opts.has("human"));
+    } else if tty {
+        print_columns(&entries, tty, terminal_width());
+    } else {
+        for entry in &entries {
+            println!("{}", entry.display_name());
+        }
+    }
+    if opts.has("recursive") {
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.is_dir() && !entry.is_dot())
+        {
+            println!();
+            if let Err(err) = list_dir(&path.join(&entry.name), opts, long, true) {
+                report_nixerror(&err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_dirs(opts: &Opts, long: bool) -> Status {
+    let paths = if opts.args.is_empty() {
+        vec!["."]
+    } else {
+        opts.args.clone()
     };
-    fs::read_dir(path)
+    let header = paths.len() > 1 || opts.has("recursive");
+    let mut status = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        if let Err(err) = list_dir(Path::new(path), opts, long, header) {
+            report_nixerror(&err);
+            status = err as i32;
+        }
+    }
+    Status::from_code(status)
+}
+
+pub fn do_dir_list(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_LIST, args);
+    Ok(list_dirs(&opts, opts.has("long")))
 }
 
-pub fn do_dir_list(_: &State, args: &[&str]) -> io::Result<Status> {
-    let entries = get_dir_entries(args)?;
-    for entry in entries {
-        if let Ok(entry) = entry {
-            print!("{}  ", entry.file_name().to_str().unwrap())
+// ********** dir.inspect **********
+
+const DIR_INSPECT: Spec = Spec::new(
+    "[DIR]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('a', "all", "Do not hide entries starting with ."),
+        Opt::flag('h', "human", "Print sizes in human readable format"),
+        Opt::flag('S', "size", "Sort by file size, largest first"),
+        Opt::flag('t', "time", "Sort by modification time, newest first"),
+        Opt::flag('r', "reverse", "Reverse the order of sorting"),
+    ],
+);
+
+pub fn do_dir_inspect(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_INSPECT, args);
+    Ok(list_dirs(&opts, true))
+}
+
+// ********** dir.tree **********
+
+const DIR_TREE: Spec = Spec::new(
+    "[DIR]",
+    0,
+    1,
+    &[
+        Opt::flag('a', "all", "Do not hide entries starting with ."),
+        Opt::value('L', "level", "N", "Descend at most N levels"),
+    ],
+);
+
+struct TreeOpts {
+    all: bool,
+    colors: bool,
+    level: Option<usize>,
+}
+
+#[derive(Default)]
+struct TreeCount {
+    dirs: usize,
+    files: usize,
+}
+
+fn print_tree(dir: &mut Dir, prefix: &str, level: usize, opts: &TreeOpts, count: &mut TreeCount) {
+    let mut entries = match read_dir_entries(dir) {
+        Ok(entries) => entries,
+        Err(err) => return report_nixerror(&err),
+    };
+    entries.retain(|entry| !entry.is_dot() && (opts.all || !entry.is_hidden()));
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        let mut line = format!(
+            "{}{}{}",
+            prefix,
+            if last { "└── " } else { "├── " },
+            colored_name(entry, opts.colors)
+        );
+        if let Some(target) = &entry.target {
+            line.push_str(" -> ");
+            line.push_str(&target.to_string_lossy());
+        }
+        println!("{}", line);
+        if !entry.is_dir() {
+            count.files += 1;
+            continue;
+        }
+        count.dirs += 1;
+        if opts.level.is_none_or(|max| level < max) {
+            match open_dir_at(dir.as_raw_fd(), &entry.name) {
+                Ok(mut subdir) => {
+                    let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                    print_tree(&mut subdir, &prefix, level + 1, opts, count);
+                }
+                Err(err) => report_nixerror(&err),
+            }
         }
     }
+}
+
+pub fn do_dir_tree(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_TREE, args);
+    let level = match opts.number("level") {
+        Ok(level) => level,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    let tree_opts = TreeOpts {
+        all: opts.has("all"),
+        colors: isatty(libc::STDOUT_FILENO),
+        level,
+    };
+    let path = opts.args.first().copied().unwrap_or(".");
+    let mut dir = Dir::open(
+        path,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+        stat::Mode::empty(),
+    )?;
+    let mut count = TreeCount::default();
+    println!("{}", path);
+    if level != Some(0) {
+        print_tree(&mut dir, "", 1, &tree_opts, &mut count);
+    }
     println!();
+    println!(
+        "{} {}, {} {}",
+        count.dirs,
+        if count.dirs == 1 {
+            "directory"
+        } else {
+            "directories"
+        },
+        count.files,
+        if count.files == 1 { "file" } else { "files" }
+    );
     Ok(Status::success())
 }
 
-pub fn do_dir_inspect(_: &State, args: &[&str]) -> io::Result<Status> {
-    let entries = get_dir_entries(args)?;
-    for entry in entries {
-        if let Ok(entry) = entry {
-            if let Ok(metadata) = entry.metadata() {
-                println!(
-                    "{:?} {}  ",
-                    metadata.len(),
-                    entry.file_name().to_str().unwrap()
-                )
+// ********** dir.copy **********
+
+const DIR_COPY: Spec = Spec::new(
+    "SOURCE DEST",
+    2,
+    2,
+    &[Opt::flag(
+        'r',
+        "recursive",
+        "Copy subdirectories recursively",
+    )],
+);
+
+fn permissions(stat: &stat::FileStat) -> stat::Mode {
+    stat::Mode::from_bits_truncate(stat.st_mode & 0o7777)
+}
+
+// copy a regular file with its permission bits
+fn copy_file_at(srcfd: RawFd, destfd: RawFd, entry: &Entry) -> nix::Result<()> {
+    let flags = OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let fdin = fcntl::openat(srcfd, entry.name.as_os_str(), flags, stat::Mode::empty())?;
+    let flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_CLOEXEC;
+    let mode = stat::Mode::S_IRUSR | stat::Mode::S_IWUSR;
+    let res = fcntl::openat(destfd, entry.name.as_os_str(), flags, mode).and_then(|fdout| {
+        let res = copy_fd(fdin, fdout).and_then(|_| stat::fchmod(fdout, permissions(&entry.stat)));
+        unistd::close(fdout)?;
+        res
+    });
+    unistd::close(fdin)?;
+    res
+}
+
+// make the destination directory, copy the contents and finally set its mode
+fn copy_dir_at(
+    srcfd: RawFd,
+    destfd: RawFd,
+    entry: &Entry,
+    path: &Path,
+    recursive: bool,
+) -> nix::Result<()> {
+    let mut src = open_dir_at(srcfd, &entry.name)?;
+    stat::mkdirat(destfd, entry.name.as_os_str(), stat::Mode::S_IRWXU)?;
+    let mut dest = open_dir_at(destfd, &entry.name)?;
+    let res = copy_tree(&mut src, &mut dest, path, recursive);
+    stat::fchmod(dest.as_raw_fd(), permissions(&entry.stat))?;
+    res
+}
+
+// copy contents of the source directory to the destination directory,
+// errors are reported and the copying continues with the next entry
+fn copy_tree(src: &mut Dir, dest: &mut Dir, path: &Path, recursive: bool) -> nix::Result<()> {
+    let (srcfd, destfd) = (src.as_raw_fd(), dest.as_raw_fd());
+    let mut res = Ok(());
+    for entry in read_dir_entries(src)? {
+        if entry.is_dot() {
+            continue;
+        }
+        let subpath = path.join(&entry.name);
+        let entry_res = match entry.kind() {
+            stat::SFlag::S_IFDIR if recursive => {
+                copy_dir_at(srcfd, destfd, &entry, &subpath, recursive)
+            }
+            stat::SFlag::S_IFDIR => {
+                eprintln!("{}: omitting directory", subpath.display());
+                continue;
+            }
+            stat::SFlag::S_IFREG => copy_file_at(srcfd, destfd, &entry),
+            stat::SFlag::S_IFLNK => match &entry.target {
+                Some(target) => {
+                    unistd::symlinkat(target.as_os_str(), Some(destfd), entry.name.as_os_str())
+                }
+                None => Err(Errno::EINVAL),
+            },
+            _ => Err(Errno::EOPNOTSUPP),
+        };
+        if let Err(err) = entry_res {
+            eprintln!("Error: {}: {}", subpath.display(), err);
+            res = Err(err);
+        }
+    }
+    res
+}
+
+// the directory or one of its parents is the directory of the stat
+fn is_inside(dirfd: RawFd, stat: &stat::FileStat) -> nix::Result<bool> {
+    let mut dirstat = stat::fstat(dirfd)?;
+    let mut dir: Option<Dir> = None;
+    loop {
+        if (dirstat.st_dev, dirstat.st_ino) == (stat.st_dev, stat.st_ino) {
+            return Ok(true);
+        }
+        let fd = dir.as_ref().map_or(dirfd, Dir::as_raw_fd);
+        let parent = open_dir_at(fd, OsStr::new(".."))?;
+        let parentstat = stat::fstat(parent.as_raw_fd())?;
+        // the parent of the root is the root
+        if (parentstat.st_dev, parentstat.st_ino) == (dirstat.st_dev, dirstat.st_ino) {
+            return Ok(false);
+        }
+        dir = Some(parent);
+        dirstat = parentstat;
+    }
+}
+
+pub fn do_dir_copy(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_COPY, args);
+    let (src, dest) = (Path::new(opts.args[0]), Path::new(opts.args[1]));
+    // copy into an existing directory or make a new one
+    let (parent, name) = match (dest.is_dir(), src.file_name()) {
+        (true, Some(name)) => (dest, name),
+        _ => (
+            dest.parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(".")),
+            dest.file_name().ok_or(Errno::EINVAL)?,
+        ),
+    };
+    let srcstat = stat::stat(src)?;
+    let mut srcdir = Dir::open(
+        src,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+        stat::Mode::empty(),
+    )?;
+    let destdir = Dir::open(
+        parent,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+        stat::Mode::empty(),
+    )?;
+    // the copy would be copied again and again
+    if is_inside(destdir.as_raw_fd(), &srcstat)? {
+        eprintln!(
+            "{}: cannot copy a directory, '{}', into itself, '{}'",
+            args[0],
+            src.display(),
+            dest.display()
+        );
+        return Ok(Status::from_code(1));
+    }
+    stat::mkdirat(destdir.as_raw_fd(), name, stat::Mode::S_IRWXU)?;
+    let mut dest = open_dir_at(destdir.as_raw_fd(), name)?;
+    let res = copy_tree(&mut srcdir, &mut dest, src, opts.has("recursive"));
+    stat::fchmod(dest.as_raw_fd(), permissions(&srcstat))?;
+    Ok(Status::from_code(res.err().map_or(0, |err| err as i32)))
+}
+
+// ********** dir.size **********
+
+const DIR_SIZE: Spec = Spec::new(
+    "[DIR]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('s', "summarize", "Print only the total for each argument"),
+        Opt::flag('h', "human", "Print sizes in human readable format"),
+        Opt::value(
+            'd',
+            "max-depth",
+            "N",
+            "Print totals of directories at most N levels deep",
+        ),
+    ],
+);
+
+struct SizeOpts {
+    human: bool,
+    depth: Option<usize>,
+    seen: HashSet<(u64, u64)>,
+}
+
+// disk usage of the directory in bytes, hard links are counted once
+fn dir_size(dir: &mut Dir, path: &Path, level: usize, opts: &mut SizeOpts) -> i64 {
+    let entries = match read_dir_entries(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Error: {}: {}", path.display(), err);
+            return 0;
+        }
+    };
+    let mut total = 0;
+    for entry in entries.iter().filter(|entry| entry.name != "..") {
+        if entry.stat.st_nlink > 1
+            && !entry.is_dir()
+            && !opts.seen.insert((entry.stat.st_dev, entry.stat.st_ino))
+        {
+            continue;
+        }
+        if entry.is_dot() || !entry.is_dir() {
+            total += entry.stat.st_blocks * 512;
+            continue;
+        }
+        let subpath = path.join(&entry.name);
+        match open_dir_at(dir.as_raw_fd(), &entry.name) {
+            Ok(mut subdir) => total += dir_size(&mut subdir, &subpath, level + 1, opts),
+            Err(err) => eprintln!("Error: {}: {}", subpath.display(), err),
+        }
+    }
+    if opts.depth.is_none_or(|depth| level <= depth) {
+        let size = if opts.human {
+            human_size(total)
+        } else {
+            (total / 1024).to_string()
+        };
+        println!("{}\t{}", size, path.display());
+    }
+    total
+}
+
+pub fn do_dir_size(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_SIZE, args);
+    let depth = match opts.number("max-depth") {
+        Ok(depth) => depth,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    let mut size_opts = SizeOpts {
+        human: opts.has("human"),
+        depth: if opts.has("summarize") {
+            Some(0)
+        } else {
+            depth
+        },
+        seen: HashSet::new(),
+    };
+    let paths = if opts.args.is_empty() {
+        vec!["."]
+    } else {
+        opts.args.clone()
+    };
+    let mut status = 0;
+    for path in paths {
+        match Dir::open(
+            path,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+            stat::Mode::empty(),
+        ) {
+            Ok(mut dir) => {
+                dir_size(&mut dir, Path::new(path), 0, &mut size_opts);
+            }
+            Err(err) => {
+                eprintln!("Error: {}: {}", path, err);
+                status = err as i32;
             }
         }
     }
+    Ok(Status::from_code(status))
+}
+
+// ********** dir.temp **********
+
+const DIR_TEMP: Spec = Spec::new(
+    "[TEMPLATE]",
+    0,
+    1,
+    &[
+        Opt::value(
+            'p',
+            "tmpdir",
+            "DIR",
+            "Create it in DIR instead of $TMPDIR or /tmp",
+        ),
+        Opt::long(
+            "cleanup",
+            None,
+            "Remove it with its contents when the shell exits",
+        ),
+    ],
+);
+
+// nix has no mkdtemp
+fn mkdtemp(template: &Path) -> nix::Result<PathBuf> {
+    let mut path = CString::new(template.as_os_str().as_bytes())
+        .map_err(|_| Errno::EINVAL)?
+        .into_bytes_with_nul();
+    if unsafe { libc::mkdtemp(path.as_mut_ptr().cast()) }.is_null() {
+        return Err(Errno::last());
+    }
+    path.pop();
+    Ok(PathBuf::from(OsString::from_vec(path)))
+}
+
+pub fn do_dir_temp(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, DIR_TEMP, args);
+    let path = mkdtemp(&temp_template(state, &opts))?;
+    if opts.has("cleanup") {
+        state.add_cleanup(&path);
+    }
+    println!("{}", path.display());
     Ok(Status::success())
 }
+
+// ********** dir.find **********
+
+#[derive(Debug, Clone, Copy)]
+enum Cmp {
+    Less,
+    Equal,
+    Greater,
+}
+
+impl Cmp {
+    // split the sign of +N or -N
+    fn parse(arg: &str) -> (Cmp, &str) {
+        match arg.as_bytes().first() {
+            Some(b'+') => (Cmp::Greater, &arg[1..]),
+            Some(b'-') => (Cmp::Less, &arg[1..]),
+            _ => (Cmp::Equal, arg),
+        }
+    }
+
+    fn matches(self, value: i64, bound: i64) -> bool {
+        match self {
+            Cmp::Less => value < bound,
+            Cmp::Equal => value == bound,
+            Cmp::Greater => value > bound,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Pred {
+    Name(CString),
+    Type(stat::SFlag),
+    Size(Cmp, i64, i64),
+    Newer(i64, i64),
+    Mtime(Cmp, i64),
+    Print0,
+    Exec(Vec<String>),
+}
+
+struct Query {
+    preds: Vec<Pred>,
+    maxdepth: Option<usize>,
+    print: bool,
+    now: i64,
+}
+
+fn parse_query(args: &[&str]) -> Result<Query, String> {
+    let mut preds = Vec::new();
+    let mut maxdepth = None;
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .copied()
+                .ok_or_else(|| format!("missing argument to '{}'", arg))
+        };
+        let invalid = |value| format!("invalid argument '{}' to '{}'", value, arg);
+        let pred = match arg {
+            "-name" => Pred::Name(CString::new(value()?).map_err(|err| err.to_string())?),
+            "-type" => Pred::Type(match value()? {
+                "f" => stat::SFlag::S_IFREG,
+                "d" => stat::SFlag::S_IFDIR,
+                "l" => stat::SFlag::S_IFLNK,
+                "p" => stat::SFlag::S_IFIFO,
+                "s" => stat::SFlag::S_IFSOCK,
+                "c" => stat::SFlag::S_IFCHR,
+                "b" => stat::SFlag::S_IFBLK,
+                other => return Err(invalid(other)),
+            }),
+            "-size" => {
+                let value = value()?;
+                let (cmp, num) = Cmp::parse(value);
+                let (num, unit) = match num.char_indices().last() {
+                    Some((pos, 'c')) => (&num[..pos], 1),
+                    Some((pos, 'k')) => (&num[..pos], 1 << 10),
+                    Some((pos, 'M')) => (&num[..pos], 1 << 20),
+                    Some((pos, 'G')) => (&num[..pos], 1 << 30),
+                    _ => (num, 512),
+                };
+                Pred::Size(cmp, num.parse().map_err(|_| invalid(value))?, unit)
+            }
+            "-newer" => {
+                let value = value()?;
+                let st = stat::stat(value).map_err(|err| format!("{}: {}", value, err))?;
+                Pred::Newer(st.st_mtime, st.st_mtime_nsec)
+            }
+            "-mtime" => {
+                let value = value()?;
+                let (cmp, num) = Cmp::parse(value);
+                Pred::Mtime(cmp, num.parse().map_err(|_| invalid(value))?)
+            }
+            "-maxdepth" => {
+                let value = value()?;
+                maxdepth = Some(value.parse().map_err(|_| invalid(value))?);
+                continue;
+            }
+            "-print0" => Pred::Print0,
+            "-exec" => {
+                let cmd: Vec<String> = iter
+                    .by_ref()
+                    .take_while(|&&arg| arg != ";")
+                    .map(|&arg| String::from(arg))
+                    .collect();
+                if cmd.is_empty() {
+                    return Err(String::from("missing command to '-exec'"));
+                }
+                Pred::Exec(cmd)
+            }
+            _ => return Err(format!("unknown predicate '{}'", arg)),
+        };
+        preds.push(pred);
+    }
+    let print = !preds
+        .iter()
+        .any(|pred| matches!(pred, Pred::Print0 | Pred::Exec(_)));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64);
+    Ok(Query {
+        preds,
+        maxdepth,
+        print,
+        now,
+    })
+}
+
+// evaluate the predicates left to right, the first false one stops the evaluation
+fn find_match(state: &State, query: &Query, path: &Path, entry: &Entry) -> bool {
+    let st = &entry.stat;
+    for pred in &query.preds {
+        let ok = match pred {
+            Pred::Name(pattern) => {
+                let name = CString::new(entry.name.as_bytes()).unwrap_or_default();
+                unsafe { libc::fnmatch(pattern.as_ptr(), name.as_ptr(), 0) == 0 }
+            }
+            Pred::Type(kind) => entry.kind() == *kind,
+            Pred::Size(cmp, size, unit) => cmp.matches((st.st_size + unit - 1) / unit, *size),
+            Pred::Newer(sec, nsec) => (st.st_mtime, st.st_mtime_nsec) > (*sec, *nsec),
+            Pred::Mtime(cmp, days) => cmp.matches((query.now - st.st_mtime) / 86400, *days),
+            Pred::Print0 => {
+                print!("{}\0", path.display());
+                true
+            }
+            Pred::Exec(cmd) => {
+                // the path is an argument as it is, never parsed as shell syntax
+                let path = path.to_string_lossy();
+                let args: Vec<String> = cmd.iter().map(|arg| arg.replace("{}", &path)).collect();
+                let cmd = Command {
+                    args: args.iter().map(String::as_str).collect(),
+                    background: false,
+                    redirects: Vec::new(),
+                };
+                io::stdout().flush().ok();
+                match run_command(state, &cmd) {
+                    Ok(status) => state.set_status(&status),
+                    Err(err) => {
+                        state.set_status_code(nix::errno::errno());
+                        report_error(&err);
+                    }
+                }
+                state.status.get() == Status::success()
+            }
+        };
+        if !ok {
+            return false;
+        }
+    }
+    if query.print {
+        println!("{}", path.display());
+    }
+    true
+}
+
+fn find_walk(state: &State, query: &Query, dir: &mut Dir, path: &Path, depth: usize) {
+    let entries = match read_dir_entries(dir) {
+        Ok(entries) => entries,
+        Err(err) => return eprintln!("Error: {}: {}", path.display(), err),
+    };
+    for entry in entries.iter().filter(|entry| !entry.is_dot()) {
+        if !state.running.get() {
+            return;
+        }
+        let subpath = path.join(&entry.name);
+        find_match(state, query, &subpath, entry);
+        if entry.is_dir() && query.maxdepth.is_none_or(|max| depth < max) {
+            match open_dir_at(dir.as_raw_fd(), &entry.name) {
+                Ok(mut subdir) => find_walk(state, query, &mut subdir, &subpath, depth + 1),
+                Err(err) => eprintln!("Error: {}: {}", subpath.display(), err),
+            }
+        }
+    }
+}
+
+const DIR_FIND_HELP: &str = "Usage: dir.find [PATH]... [PREDICATE]...
+Search for files in a directory tree
+
+  -name GLOB          File name matches the shell pattern
+  -type f|d|l|p|s|c|b File is of the given type
+  -size [+-]N[ckMG]   File uses N units of space (512 byte blocks by default)
+  -newer FILE         File was modified after FILE
+  -mtime [+-]N        File was modified N days ago
+  -maxdepth N         Descend at most N levels below the starting points
+  -print0             Print the path followed by a null character
+  -exec CMD {} ;      Run the command with {} replaced by the path";
+
+pub fn do_dir_find(state: &State, args: &[&str]) -> io::Result<Status> {
+    if args.get(1) == Some(&"--help") {
+        println!("{}", DIR_FIND_HELP);
+        return Ok(Status::success());
+    }
+    // starting points come before the first predicate
+    let split = args[1..]
+        .iter()
+        .position(|arg| arg.starts_with('-') && arg.len() > 1)
+        .map_or(args.len(), |pos| pos + 1);
+    let query = match parse_query(&args[split..]) {
+        Ok(query) => query,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    let paths = if split > 1 {
+        &args[1..split]
+    } else {
+        &["."][..]
+    };
+    let mut status = 0;
+    for path in paths {
+        let entry = match stat::lstat(*path) {
+            Ok(stat) => Entry {
+                name: Path::new(path)
+                    .file_name()
+                    .unwrap_or_else(|| OsStr::new(path))
+                    .to_os_string(),
+                stat,
+                target: None,
+            },
+            Err(err) => {
+                eprintln!("Error: {}: {}", path, err);
+                status = err as i32;
+                continue;
+            }
+        };
+        find_match(state, &query, Path::new(path), &entry);
+        if entry.is_dir() && query.maxdepth != Some(0) {
+            match Dir::open(
+                *path,
+                OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+                stat::Mode::empty(),
+            ) {
+                Ok(mut dir) => find_walk(state, &query, &mut dir, Path::new(path), 1),
+                Err(err) => {
+                    eprintln!("Error: {}: {}", path, err);
+                    status = err as i32;
+                }
+            }
+        }
+    }
+    Ok(Status::from_code(status))
+}
@@ -1,24 +1,62 @@
-use nix::{fcntl, unistd};
-use std::fs;
-use std::io; // portable FS functions, TODO: use only nix::*
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::{
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+        sendfile,
+        signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+        stat,
+        time::TimeSpec,
+        uio,
+    },
+    unistd,
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::common::report_nixerror;
+use crate::builtins::dir::{file_kind, mode_string, Names};
+use crate::builtins::opts::{usage_error, Opt, Opts, Spec, MANY};
+use crate::common::{format_time, report_nixerror, FILE_MODE};
+use crate::digest;
 use crate::state::{State, Status};
 
-pub fn do_link_hard(_: &State, args: &[&str]) -> io::Result<Status> {
+const LINK_HARD: Spec = Spec::new("ORIGINAL NEW", 2, 2, &[]);
+
+pub fn do_link_hard(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, LINK_HARD, args);
     let flag = unistd::LinkatFlags::NoSymlinkFollow;
-    unistd::linkat(None, args[1], None, args[2], flag)?;
+    unistd::linkat(None, opts.args[0], None, opts.args[1], flag)?;
     Ok(Status::success())
 }
 
-pub fn do_link_soft(_: &State, args: &[&str]) -> io::Result<Status> {
-    unistd::symlinkat(args[1], None, args[2])?;
+const LINK_SOFT: Spec = Spec::new(
+    "ORIGINAL NEW",
+    2,
+    2,
+    &[Opt::flag('f', "force", "Remove the existing destination")],
+);
+
+pub fn do_link_soft(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, LINK_SOFT, args);
+    if opts.has("force") {
+        match unistd::unlink(opts.args[1]) {
+            Ok(()) | Err(Errno::ENOENT) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    unistd::symlinkat(opts.args[0], None, opts.args[1])?;
     Ok(Status::success())
 }
 
-pub fn do_link_read(_: &State, args: &[&str]) -> io::Result<Status> {
+const LINK_READ: Spec = Spec::new("LINK...", 1, MANY, &[]);
+
+pub fn do_link_read(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, LINK_READ, args);
     let mut status = 0;
-    for arg in &args[1..] {
+    for arg in &opts.args {
         let path = std::path::PathBuf::from(arg);
         match fcntl::readlink(&path) {
             Ok(path) => println!("{}", path.to_str().unwrap()),
@@ -31,67 +69,1175 @@ pub fn do_link_read(_: &State, args: &[&str]) -> io::Result<Status> {
     Ok(Status::from_code(status))
 }
 
-pub fn do_unlink(_: &State, args: &[&str]) -> io::Result<Status> {
+const UNLINK: Spec = Spec::new(
+    "FILE...",
+    1,
+    MANY,
+    &[Opt::flag('f', "force", "Ignore nonexistent files")],
+);
+
+pub fn do_unlink(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, UNLINK, args);
     let mut status = 0;
-    for arg in &args[1..] {
+    for arg in &opts.args {
         let path = std::path::PathBuf::from(arg);
-        if let Err(err) = unistd::unlink(&path) {
-            report_nixerror(&err);
-            status = nix::errno::errno();
+        match unistd::unlink(&path) {
+            Ok(()) => {}
+            Err(Errno::ENOENT) if opts.has("force") => {}
+            Err(err) => {
+                report_nixerror(&err);
+                status = nix::errno::errno();
+            }
         }
     }
     Ok(Status::from_code(status))
 }
 
-pub fn do_rename(_: &State, args: &[&str]) -> io::Result<Status> {
-    fcntl::renameat(None, args[1], None, args[2])?;
+const RENAME: Spec = Spec::new(
+    "SOURCE DEST",
+    2,
+    2,
+    &[Opt::flag(
+        'n',
+        "no-clobber",
+        "Do not overwrite an existing file",
+    )],
+);
+
+pub fn do_rename(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, RENAME, args);
+    let (source, dest) = (opts.args[0], opts.args[1]);
+    if opts.has("no-clobber") {
+        fcntl::renameat2(
+            None,
+            source,
+            None,
+            dest,
+            fcntl::RenameFlags::RENAME_NOREPLACE,
+        )?;
+    } else {
+        fcntl::renameat(None, source, None, dest)?;
+    }
     Ok(Status::success())
 }
 
-// pub fn do_cpcat1(state: &mut State, args: &[&str]) {
-//     state.status = 0;
-//     let fin = if args[1] == "-" {
-//         0
-//     } else {
-//         match fcntl::open(args[1], fcntl::OFlag::O_RDONLY, stat::Mode::S_IRWXU) {
-//             Ok(fd) => fd,
-//             Err(err) => {
-//                 handle_nixerror(state, &err);
-//                 return;
-//             }
-//         }
-//     };
-// }
-
-pub fn do_cpcat(_: &State, args: &[&str]) -> io::Result<Status> {
-    if args.len() < 3 {
-        return Ok(Status::from_code(127));
-    }
-    // open input
-    let mut fin: Box<dyn io::Read> = if args[1] == "-" {
-        Box::new(io::stdin())
+// write the whole buffer, retrying on short writes and interrupts
+fn write_all(fd: RawFd, mut buf: &[u8]) -> nix::Result<()> {
+    while !buf.is_empty() {
+        match unistd::write(fd, buf) {
+            Ok(count) => buf = &buf[count..],
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// is the fast path unavailable for this pair of descriptors
+fn unsupported(err: Errno) -> bool {
+    matches!(
+        err,
+        Errno::EINVAL | Errno::ENOSYS | Errno::EXDEV | Errno::EBADF | Errno::EOPNOTSUPP
+    )
+}
+
+// in-kernel copy, None if not supported before anything was copied
+fn copy_fast(fdin: RawFd, fdout: RawFd, both_regular: bool) -> Option<nix::Result<u64>> {
+    const CHUNK: usize = 1 << 30;
+    let mut total = 0;
+    loop {
+        let res = if both_regular {
+            fcntl::copy_file_range(fdin, None, fdout, None, CHUNK)
+        } else {
+            sendfile::sendfile(fdout, fdin, None, CHUNK)
+        };
+        match res {
+            Ok(0) => return Some(Ok(total)),
+            Ok(count) => total += count as u64,
+            Err(Errno::EINTR) => continue,
+            Err(err) if total == 0 && unsupported(err) => return None,
+            Err(err) => return Some(Err(err)),
+        }
+    }
+}
+
+fn is_regular(fd: RawFd) -> bool {
+    stat::fstat(fd).is_ok_and(|st| file_kind(st.st_mode) == stat::SFlag::S_IFREG)
+}
+
+/// Copy the rest of the input to the output, returns the number of bytes copied.
+/// Uses copy_file_range or sendfile if the input is a regular file.
+pub fn copy_fd(fdin: RawFd, fdout: RawFd) -> nix::Result<u64> {
+    if is_regular(fdin) {
+        if let Some(res) = copy_fast(fdin, fdout, is_regular(fdout)) {
+            return res;
+        }
+    }
+    let mut buf = vec![0; 128 * 1024];
+    let mut total = 0;
+    loop {
+        let count = match unistd::read(fdin, &mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(count) => count,
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err),
+        };
+        write_all(fdout, &buf[..count])?;
+        total += count as u64;
+    }
+}
+
+const CPCAT: Spec = Spec::new(
+    "SOURCE DEST",
+    2,
+    2,
+    &[
+        Opt::flag('a', "append", "Append to the destination"),
+        Opt::flag('n', "no-clobber", "Do not overwrite an existing file"),
+    ],
+);
+
+fn cpcat(fdin: RawFd, dest: &str, append: bool, noclobber: bool) -> nix::Result<()> {
+    let srcstat = stat::fstat(fdin)?;
+    if dest == "-" {
+        return copy_fd(fdin, libc::STDOUT_FILENO).map(drop);
+    }
+    // copying a file onto itself would destroy or endlessly grow it
+    if let Ok(st) = stat::stat(dest) {
+        if (st.st_dev, st.st_ino) == (srcstat.st_dev, srcstat.st_ino) && is_regular(fdin) {
+            return Err(Errno::EINVAL);
+        }
+    }
+    let mut flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+    flags |= if append {
+        OFlag::O_APPEND
     } else {
-        Box::new(fs::OpenOptions::new().read(true).open(args[1])?)
+        OFlag::O_TRUNC
     };
-    // open output
-    let mut fout: Box<dyn io::Write> = if args[2] == "-" {
-        Box::new(io::stdout())
+    if noclobber {
+        flags |= OFlag::O_EXCL;
+    }
+    // a new file gets the mode of a regular source file
+    let mode = if is_regular(fdin) {
+        stat::Mode::from_bits_truncate(srcstat.st_mode & 0o777)
     } else {
-        Box::new(
-            fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(args[2])?,
-        )
+        FILE_MODE
+    };
+    let fdout = fcntl::open(dest, flags, mode)?;
+    let res = copy_fd(fdin, fdout).map(drop);
+    unistd::close(fdout)?;
+    res
+}
+
+pub fn do_cpcat(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, CPCAT, args);
+    let (source, dest) = (opts.args[0], opts.args[1]);
+    let fdin = if source == "-" {
+        libc::STDIN_FILENO
+    } else {
+        fcntl::open(
+            source,
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            stat::Mode::empty(),
+        )?
     };
-    // do the copy
-    let mut buf = [0; 4096];
+    io::stdout().flush()?;
+    let res = cpcat(fdin, dest, opts.has("append"), opts.has("no-clobber"));
+    if fdin != libc::STDIN_FILENO {
+        unistd::close(fdin)?;
+    }
+    res?;
+    Ok(Status::success())
+}
+
+// ********** file content **********
+
+// open a file for reading, `-` is the standard input
+fn open_input(path: &str) -> nix::Result<RawFd> {
+    if path == "-" {
+        Ok(libc::STDIN_FILENO)
+    } else {
+        fcntl::open(
+            path,
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            stat::Mode::empty(),
+        )
+    }
+}
+
+fn close_input(fd: RawFd) {
+    if fd != libc::STDIN_FILENO {
+        unistd::close(fd).ok();
+    }
+}
+
+// feed the input to the callback chunk by chunk until it returns false
+fn read_chunks<F>(fd: RawFd, mut callback: F) -> nix::Result<()>
+where
+    F: FnMut(&[u8]) -> nix::Result<bool>,
+{
+    let mut buf = vec![0; 64 * 1024];
     loop {
-        let count = fin.read(&mut buf)?;
-        if count == 0 {
-            break;
+        let count = match unistd::read(fd, &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(count) => count,
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err),
+        };
+        if !callback(&buf[..count])? {
+            return Ok(());
+        }
+    }
+}
+
+// run the callback on every input, the standard input if there are none,
+// errors are reported and the last one becomes the status
+fn for_each_input<F>(inputs: &[&str], mut callback: F) -> Status
+where
+    F: FnMut(usize, &str, RawFd) -> nix::Result<()>,
+{
+    let inputs: &[&str] = if inputs.is_empty() { &["-"] } else { inputs };
+    let mut status = 0;
+    for (i, path) in inputs.iter().enumerate() {
+        let res = open_input(path).and_then(|fd| {
+            let res = callback(i, path, fd);
+            close_input(fd);
+            res
+        });
+        if let Err(err) = res {
+            eprintln!("Error: {}: {}", path, err);
+            status = err as i32;
+        }
+    }
+    Status::from_code(status)
+}
+
+// print the `==> name <==` header used when there are several inputs
+fn write_header(index: usize, path: &str) -> nix::Result<()> {
+    let sep = if index > 0 { "\n" } else { "" };
+    let name = if path == "-" { "standard input" } else { path };
+    write_all(
+        libc::STDOUT_FILENO,
+        format!("{}==> {} <==\n", sep, name).as_bytes(),
+    )
+}
+
+// number of lines or bytes to print, -n takes precedence over -c
+fn head_tail_count(opts: &Opts) -> Result<(u64, bool), String> {
+    if let Some(count) = opts.number::<u64>("lines")? {
+        Ok((count, true))
+    } else if let Some(count) = opts.number::<u64>("bytes")? {
+        Ok((count, false))
+    } else {
+        Ok((10, true))
+    }
+}
+
+// ********** file.head **********
+
+const FILE_HEAD: Spec = Spec::new(
+    "[FILE]...",
+    0,
+    MANY,
+    &[
+        Opt::value('n', "lines", "N", "Print the first N lines instead of 10"),
+        Opt::value('c', "bytes", "N", "Print the first N bytes"),
+    ],
+);
+
+fn head(fd: RawFd, count: u64, lines: bool) -> nix::Result<()> {
+    let mut left = count;
+    if left == 0 {
+        return Ok(());
+    }
+    read_chunks(fd, |chunk| {
+        let mut end = chunk.len();
+        if lines {
+            for (i, _) in chunk.iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+                left -= 1;
+                if left == 0 {
+                    end = i + 1;
+                    break;
+                }
+            }
+        } else {
+            end = end.min(left as usize);
+            left -= end as u64;
+        }
+        write_all(libc::STDOUT_FILENO, &chunk[..end])?;
+        Ok(left > 0)
+    })
+}
+
+pub fn do_file_head(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_HEAD, args);
+    let (count, lines) = match head_tail_count(&opts) {
+        Ok(res) => res,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    io::stdout().flush()?;
+    let headers = opts.args.len() > 1;
+    Ok(for_each_input(&opts.args, |i, path, fd| {
+        if headers {
+            write_header(i, path)?;
+        }
+        head(fd, count, lines)
+    }))
+}
+
+// ********** file.tail **********
+
+const FILE_TAIL: Spec = Spec::new(
+    "[FILE]...",
+    0,
+    MANY,
+    &[
+        Opt::value('n', "lines", "N", "Print the last N lines instead of 10"),
+        Opt::value('c', "bytes", "N", "Print the last N bytes"),
+        Opt::flag('f', "follow", "Print appended data as the files grow"),
+    ],
+);
+
+// offset of the last `count` lines or bytes of the data,
+// 0 if the data may not contain all of them
+fn tail_start(data: &[u8], count: u64, lines: bool) -> usize {
+    if !lines {
+        return data.len().saturating_sub(count as usize);
+    }
+    if count == 0 {
+        return data.len();
+    }
+    // the final newline ends the last line
+    let end = data.len() - usize::from(data.last() == Some(&b'\n'));
+    let mut left = count;
+    for (i, _) in data[..end]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, &byte)| byte == b'\n')
+    {
+        left -= 1;
+        if left == 0 {
+            return i + 1;
+        }
+    }
+    0
+}
+
+// read a regular file backwards until the tail is found
+fn tail_file(fd: RawFd, count: u64, lines: bool) -> nix::Result<()> {
+    const BLOCK: i64 = 64 * 1024;
+    let mut offset = stat::fstat(fd)?.st_size;
+    let mut data = Vec::new();
+    let mut start = 0;
+    while offset > 0 && start == 0 {
+        let size = offset.min(BLOCK);
+        offset -= size;
+        let mut block = vec![0; size as usize];
+        let mut filled = 0;
+        while filled < block.len() {
+            match uio::pread(fd, &mut block[filled..], offset + filled as i64) {
+                Ok(0) => break,
+                Ok(count) => filled += count,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        block.truncate(filled);
+        block.extend_from_slice(&data);
+        data = block;
+        start = tail_start(&data, count, lines);
+    }
+    write_all(libc::STDOUT_FILENO, &data[start..])?;
+    // following continues at the end of the file
+    unistd::lseek(fd, 0, unistd::Whence::SeekEnd).map(drop)
+}
+
+// read a pipe or terminal to the end keeping only the tail
+fn tail_stream(fd: RawFd, count: u64, lines: bool) -> nix::Result<()> {
+    let mut data = Vec::new();
+    read_chunks(fd, |chunk| {
+        data.extend_from_slice(chunk);
+        if data.len() > 1024 * 1024 {
+            let start = tail_start(&data, count, lines);
+            data.drain(..start);
         }
-        fout.write(&buf[0..count])?;
+        Ok(true)
+    })?;
+    let start = tail_start(&data, count, lines);
+    write_all(libc::STDOUT_FILENO, &data[start..])
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// print data appended to the files until interrupted
+fn follow(files: &[(&str, RawFd)], headers: bool) -> nix::Result<()> {
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+    let mut watches = HashMap::new();
+    for (i, &(path, fd)) in files.iter().enumerate() {
+        // the standard input is watched by the file its descriptor refers to
+        let path = if path == "-" {
+            format!("/proc/self/fd/{}", fd)
+        } else {
+            path.to_string()
+        };
+        let wd = inotify.add_watch(path.as_str(), AddWatchFlags::IN_MODIFY)?;
+        watches.insert(wd, i);
+    }
+    // interrupt the blocking read instead of killing the shell
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let action = SigAction::new(
+        SigHandler::Handler(interrupt),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    let old = unsafe { signal::sigaction(Signal::SIGINT, &action)? };
+    let mut last = files.len() - 1;
+    let res = loop {
+        // interrupted while the previous events were printed
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EINTR) if INTERRUPTED.load(Ordering::SeqCst) => break Ok(()),
+            Err(Errno::EINTR) => continue,
+            Err(err) => break Err(err),
+        };
+        let res = events.iter().try_for_each(|event| {
+            let i = match watches.get(&event.wd) {
+                Some(&i) => i,
+                None => return Ok(()),
+            };
+            let (path, fd) = files[i];
+            let size = stat::fstat(fd)?.st_size;
+            let offset = unistd::lseek(fd, 0, unistd::Whence::SeekCur)?;
+            if size < offset {
+                eprintln!("file.tail: {}: file truncated", path);
+                unistd::lseek(fd, 0, unistd::Whence::SeekSet)?;
+            } else if size == offset {
+                return Ok(());
+            }
+            if headers && i != last {
+                write_header(1, path)?;
+                last = i;
+            }
+            copy_fd(fd, libc::STDOUT_FILENO).map(drop)
+        });
+        if res.is_err() {
+            break res;
+        }
+    };
+    unsafe { signal::sigaction(Signal::SIGINT, &old)? };
+    unistd::close(inotify.as_raw_fd())?;
+    res
+}
+
+pub fn do_file_tail(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_TAIL, args);
+    let (count, lines) = match head_tail_count(&opts) {
+        Ok(res) => res,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    io::stdout().flush()?;
+    let headers = opts.args.len() > 1;
+    let follow_files = opts.has("follow");
+    // the followed files stay open until the end
+    let mut followed = Vec::new();
+    let mut status = for_each_input(&opts.args, |i, path, fd| {
+        if headers {
+            write_header(i, path)?;
+        }
+        if is_regular(fd) {
+            tail_file(fd, count, lines)?;
+            if follow_files {
+                followed.push((path.to_string(), unistd::dup(fd)?));
+            }
+            Ok(())
+        } else {
+            tail_stream(fd, count, lines)
+        }
+    });
+    if !followed.is_empty() {
+        let files: Vec<_> = followed
+            .iter()
+            .map(|(path, fd)| (path.as_str(), *fd))
+            .collect();
+        if let Err(err) = follow(&files, headers) {
+            eprintln!("Error: {}", err);
+            status = Status::from_code(err as i32);
+        }
+        for (_, fd) in followed {
+            unistd::close(fd)?;
+        }
+    }
+    Ok(status)
+}
+
+// ********** file.count **********
+
+const FILE_COUNT: Spec = Spec::new(
+    "[FILE]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('l', "lines", "Print the number of lines"),
+        Opt::flag('w', "words", "Print the number of words"),
+        Opt::flag('c', "bytes", "Print the number of bytes"),
+    ],
+);
+
+#[derive(Default, Clone, Copy)]
+struct Counts {
+    lines: u64,
+    words: u64,
+    bytes: u64,
+}
+
+fn count(fd: RawFd) -> nix::Result<Counts> {
+    let mut counts = Counts::default();
+    let mut in_word = false;
+    read_chunks(fd, |chunk| {
+        for &byte in chunk {
+            if byte == b'\n' {
+                counts.lines += 1;
+            }
+            // the C locale whitespace, including the vertical tab
+            if byte.is_ascii_whitespace() || byte == 0x0b {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                counts.words += 1;
+            }
+        }
+        counts.bytes += chunk.len() as u64;
+        Ok(true)
+    })?;
+    Ok(counts)
+}
+
+pub fn do_file_count(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_COUNT, args);
+    let all = !(opts.has("lines") || opts.has("words") || opts.has("bytes"));
+    let show = [
+        all || opts.has("lines"),
+        all || opts.has("words"),
+        all || opts.has("bytes"),
+    ];
+    let mut rows = Vec::new();
+    let mut total = Counts::default();
+    let status = for_each_input(&opts.args, |_, path, fd| {
+        let counts = count(fd)?;
+        total.lines += counts.lines;
+        total.words += counts.words;
+        total.bytes += counts.bytes;
+        let name = if opts.args.is_empty() { "" } else { path };
+        rows.push((counts, name.to_string()));
+        Ok(())
+    });
+    if rows.len() > 1 {
+        rows.push((total, "total".to_string()));
+    }
+    // align the columns to the widest number
+    let width = if show.iter().filter(|&&col| col).count() > 1 || rows.len() > 1 {
+        total.bytes.max(total.lines).to_string().len()
+    } else {
+        1
+    };
+    for (counts, name) in rows {
+        let values = [counts.lines, counts.words, counts.bytes];
+        let mut line: Vec<_> = values
+            .iter()
+            .zip(show)
+            .filter(|(_, col)| *col)
+            .map(|(value, _)| format!("{:>1$}", value, width))
+            .collect();
+        if !name.is_empty() {
+            line.push(name);
+        }
+        println!("{}", line.join(" "));
+    }
+    Ok(status)
+}
+
+// ********** file.hash **********
+
+const FILE_HASH: Spec = Spec::new(
+    "[FILE]...",
+    0,
+    MANY,
+    &[Opt::value(
+        'a',
+        "algorithm",
+        "NAME",
+        "Use sha256 (the default) or md5",
+    )],
+);
+
+pub fn do_file_hash(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_HASH, args);
+    let name = opts.value("algorithm").unwrap_or("sha256");
+    if digest::by_name(name).is_none() {
+        return Ok(usage_error(
+            args[0],
+            &format!("unknown algorithm '{}'", name),
+        ));
+    }
+    Ok(for_each_input(&opts.args, |_, path, fd| {
+        let mut digest = digest::by_name(name).unwrap();
+        read_chunks(fd, |chunk| {
+            digest.update(chunk);
+            Ok(true)
+        })?;
+        println!("{}  {}", digest::to_hex(&digest.finish()), path);
+        Ok(())
+    }))
+}
+
+// ********** modes **********
+
+/// Apply an octal or symbolic (e.g. `u+x,go-w` or `a=r`) mode to the given mode.
+/// Symbolic clauses without the who letters do not affect the bits set in umask.
+pub fn parse_mode(spec: &str, mode: u32, umask: u32, is_dir: bool) -> Result<u32, String> {
+    let invalid = || format!("invalid mode '{}'", spec);
+    if !spec.is_empty() && spec.chars().all(|ch| ch.is_digit(8)) {
+        return u32::from_str_radix(spec, 8)
+            .ok()
+            .filter(|&mode| mode <= 0o7777)
+            .ok_or_else(invalid);
+    }
+    let mut mode = mode & 0o7777;
+    for clause in spec.split(',') {
+        let who_end = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+        let mut who = 0;
+        for ch in clause[..who_end].chars() {
+            who |= match ch {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return Err(invalid()),
+            };
+        }
+        let mask = if who == 0 { 0o7777 & !umask } else { who };
+        let who = if who == 0 { 0o7777 } else { who };
+        // sequence of operators, each followed by permissions or a class to copy from
+        let mut rest = &clause[who_end..];
+        while let Some(op) = rest.chars().next() {
+            rest = &rest[1..];
+            let end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+            let mut bits = 0;
+            for ch in rest[..end].chars() {
+                bits |= match ch {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    'u' => ((mode >> 6) & 0o7) * 0o111,
+                    'g' => ((mode >> 3) & 0o7) * 0o111,
+                    'o' => (mode & 0o7) * 0o111,
+                    _ => return Err(invalid()),
+                };
+            }
+            rest = &rest[end..];
+            match op {
+                '+' => mode |= bits & who & mask,
+                '-' => mode &= !(bits & who & mask),
+                _ => mode = (mode & !who) | (bits & who & mask),
+            }
+        }
+    }
+    Ok(mode)
+}
+
+// ********** file.stat **********
+
+const FILE_STAT: Spec = Spec::new(
+    "FILE...",
+    1,
+    MANY,
+    &[
+        Opt::flag('L', "dereference", "Follow symbolic links"),
+        Opt::value('c', "format", "FORMAT", "Print the fields given by FORMAT"),
+    ],
+);
+
+const FILE_STAT_FORMAT: &str = "  File: %N
+  Size: %-11s Blocks: %-10b IO Block: %-6o %F
+Device: %Dh/%dd\tInode: %-11i Links: %h
+Access: (%a/%A)  Uid: (%5u/%8U)   Gid: (%5g/%8G)
+Access: %x
+Modify: %y
+Change: %z";
+
+fn type_name(st: &stat::FileStat) -> &'static str {
+    match file_kind(st.st_mode) {
+        stat::SFlag::S_IFREG if st.st_size == 0 => "regular empty file",
+        stat::SFlag::S_IFREG => "regular file",
+        stat::SFlag::S_IFDIR => "directory",
+        stat::SFlag::S_IFLNK => "symbolic link",
+        stat::SFlag::S_IFIFO => "fifo",
+        stat::SFlag::S_IFSOCK => "socket",
+        stat::SFlag::S_IFCHR => "character special file",
+        stat::SFlag::S_IFBLK => "block special file",
+        _ => "unknown",
+    }
+}
+
+fn stat_time(sec: i64, nsec: i64) -> String {
+    format!(
+        "{}.{:09} {}",
+        format_time(sec, "%Y-%m-%d %H:%M:%S"),
+        nsec,
+        format_time(sec, "%z")
+    )
+}
+
+// expand the format directives (%s, %-11s, ...) for the file
+fn format_stat(format: &str, path: &str, st: &stat::FileStat, names: &mut Names) -> String {
+    let mut res = String::new();
+    let mut iter = format.chars().peekable();
+    while let Some(ch) = iter.next() {
+        if ch != '%' {
+            res.push(ch);
+            continue;
+        }
+        // optional alignment and width
+        let left = iter.next_if_eq(&'-').is_some();
+        let mut width = 0;
+        while let Some(digit) = iter.peek().and_then(|ch| ch.to_digit(10)) {
+            width = width * 10 + digit as usize;
+            iter.next();
+        }
+        let field = match iter.next() {
+            Some('n') => String::from(path),
+            Some('N') => match fcntl::readlink(path) {
+                Ok(target) if file_kind(st.st_mode) == stat::SFlag::S_IFLNK => {
+                    format!("'{}' -> '{}'", path, target.to_string_lossy())
+                }
+                _ => format!("'{}'", path),
+            },
+            Some('s') => st.st_size.to_string(),
+            Some('b') => st.st_blocks.to_string(),
+            Some('o') => st.st_blksize.to_string(),
+            Some('F') => String::from(type_name(st)),
+            Some('d') => st.st_dev.to_string(),
+            Some('D') => format!("{:x}", st.st_dev),
+            Some('i') => st.st_ino.to_string(),
+            Some('h') => st.st_nlink.to_string(),
+            Some('a') => format!("{:04o}", st.st_mode & 0o7777),
+            Some('A') => mode_string(st.st_mode),
+            Some('u') => st.st_uid.to_string(),
+            Some('U') => String::from(names.user(st.st_uid)),
+            Some('g') => st.st_gid.to_string(),
+            Some('G') => String::from(names.group(st.st_gid)),
+            Some('x') => stat_time(st.st_atime, st.st_atime_nsec),
+            Some('y') => stat_time(st.st_mtime, st.st_mtime_nsec),
+            Some('z') => stat_time(st.st_ctime, st.st_ctime_nsec),
+            Some('X') => st.st_atime.to_string(),
+            Some('Y') => st.st_mtime.to_string(),
+            Some('Z') => st.st_ctime.to_string(),
+            Some('%') => String::from("%"),
+            Some(other) => format!("%{}", other),
+            None => String::from("%"),
+        };
+        if left {
+            res.push_str(&format!("{:<width$}", field, width = width));
+        } else {
+            res.push_str(&format!("{:>width$}", field, width = width));
+        }
+    }
+    res
+}
+
+pub fn do_file_stat(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_STAT, args);
+    let format = opts.value("format").unwrap_or(FILE_STAT_FORMAT);
+    let mut names = Names::default();
+    let mut status = 0;
+    for arg in &opts.args {
+        let res = if opts.has("dereference") {
+            stat::stat(*arg)
+        } else {
+            stat::lstat(*arg)
+        };
+        match res {
+            Ok(st) => println!("{}", format_stat(format, arg, &st, &mut names)),
+            Err(err) => {
+                eprintln!("Error: {}: {}", arg, err);
+                status = err as i32;
+            }
+        }
+    }
+    Ok(Status::from_code(status))
+}
+
+// ********** file.chmod **********
+
+const FILE_CHMOD: Spec = Spec::new("MODE FILE...", 2, MANY, &[]);
+
+pub fn do_file_chmod(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_CHMOD, args);
+    let umask = state.umask.get();
+    let mut status = 0;
+    for arg in &opts.args[1..] {
+        let res = stat::stat(*arg).and_then(|st| {
+            let is_dir = file_kind(st.st_mode) == stat::SFlag::S_IFDIR;
+            let mode = match parse_mode(opts.args[0], st.st_mode, umask, is_dir) {
+                Ok(mode) => mode,
+                Err(msg) => return Ok(Err(msg)),
+            };
+            let mode = stat::Mode::from_bits_truncate(mode);
+            stat::fchmodat(None, *arg, mode, stat::FchmodatFlags::FollowSymlink).map(Ok)
+        });
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(msg)) => return Ok(usage_error(args[0], &msg)),
+            Err(err) => {
+                eprintln!("Error: {}: {}", arg, err);
+                status = err as i32;
+            }
+        }
+    }
+    Ok(Status::from_code(status))
+}
+
+// ********** file.chown **********
+
+const FILE_CHOWN: Spec = Spec::new(
+    "[USER][:GROUP] FILE...",
+    2,
+    MANY,
+    &[Opt::flag(
+        'h',
+        "no-dereference",
+        "Change symbolic links instead of their targets",
+    )],
+);
+
+// user and group given by their names or numeric ids
+fn parse_owner(spec: &str) -> Result<(Option<unistd::Uid>, Option<unistd::Gid>), String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, group),
+        None => (spec, ""),
+    };
+    let uid = match user {
+        "" => None,
+        _ => match unistd::User::from_name(user) {
+            Ok(Some(user)) => Some(user.uid),
+            _ => Some(unistd::Uid::from_raw(
+                user.parse()
+                    .map_err(|_| format!("invalid user '{}'", user))?,
+            )),
+        },
+    };
+    let gid = match group {
+        "" => None,
+        _ => match unistd::Group::from_name(group) {
+            Ok(Some(group)) => Some(group.gid),
+            _ => Some(unistd::Gid::from_raw(
+                group
+                    .parse()
+                    .map_err(|_| format!("invalid group '{}'", group))?,
+            )),
+        },
+    };
+    Ok((uid, gid))
+}
+
+pub fn do_file_chown(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_CHOWN, args);
+    let (uid, gid) = match parse_owner(opts.args[0]) {
+        Ok(owner) => owner,
+        Err(msg) => return Ok(usage_error(args[0], &msg)),
+    };
+    let flag = if opts.has("no-dereference") {
+        unistd::FchownatFlags::NoFollowSymlink
+    } else {
+        unistd::FchownatFlags::FollowSymlink
+    };
+    let mut status = 0;
+    for arg in &opts.args[1..] {
+        if let Err(err) = unistd::fchownat(None, *arg, uid, gid, flag) {
+            eprintln!("Error: {}: {}", arg, err);
+            status = err as i32;
+        }
+    }
+    Ok(Status::from_code(status))
+}
+
+// ********** file.touch **********
+
+const FILE_TOUCH: Spec = Spec::new(
+    "FILE...",
+    1,
+    MANY,
+    &[
+        Opt::flag('a', "access", "Change only the access time"),
+        Opt::flag('m', "modification", "Change only the modification time"),
+        Opt::flag('c', "no-create", "Do not create files"),
+        Opt::value('r', "reference", "FILE", "Use the times of FILE"),
+        Opt::value(
+            't',
+            "time",
+            "STAMP",
+            "Use [[CC]YY]MMDDhhmm[.ss] instead of now",
+        ),
+    ],
+);
+
+const UTIME_NOW: (i64, i64) = (0, libc::UTIME_NOW);
+const UTIME_OMIT: (i64, i64) = (0, libc::UTIME_OMIT);
+
+fn timespec((sec, nsec): (i64, i64)) -> TimeSpec {
+    TimeSpec::from(libc::timespec {
+        tv_sec: sec,
+        tv_nsec: nsec,
+    })
+}
+
+// local time given as [[CC]YY]MMDDhhmm[.ss]
+fn parse_stamp(stamp: &str) -> Option<i64> {
+    let (digits, secs) = match stamp.split_once('.') {
+        Some((digits, secs)) if secs.len() == 2 => (digits, secs.parse().ok()?),
+        Some(_) => return None,
+        None => (stamp, 0),
+    };
+    if !digits.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    let num = |from: usize| digits[from..from + 2].parse::<i32>().ok();
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    let rest = match digits.len() {
+        8 => 0,
+        10 => {
+            let year = num(0)?;
+            tm.tm_year = if year < 69 { year + 100 } else { year };
+            2
+        }
+        12 => {
+            tm.tm_year = digits[..4].parse::<i32>().ok()? - 1900;
+            4
+        }
+        _ => return None,
+    };
+    tm.tm_mon = num(rest)? - 1;
+    tm.tm_mday = num(rest + 2)?;
+    tm.tm_hour = num(rest + 4)?;
+    tm.tm_min = num(rest + 6)?;
+    tm.tm_sec = secs;
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        time => Some(time),
+    }
+}
+
+pub fn do_file_touch(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_TOUCH, args);
+    let (mut atime, mut mtime) = (UTIME_NOW, UTIME_NOW);
+    if let Some(reference) = opts.value("reference") {
+        let st = stat::stat(reference)?;
+        atime = (st.st_atime, st.st_atime_nsec);
+        mtime = (st.st_mtime, st.st_mtime_nsec);
+    }
+    if let Some(stamp) = opts.value("time") {
+        match parse_stamp(stamp) {
+            Some(time) => (atime, mtime) = ((time, 0), (time, 0)),
+            None => return Ok(usage_error(args[0], &format!("invalid time '{}'", stamp))),
+        }
+    }
+    if opts.has("access") && !opts.has("modification") {
+        mtime = UTIME_OMIT;
+    }
+    if opts.has("modification") && !opts.has("access") {
+        atime = UTIME_OMIT;
+    }
+    let mut status = 0;
+    for arg in &opts.args {
+        let res = match stat::stat(*arg) {
+            Err(Errno::ENOENT) if opts.has("no-create") => continue,
+            Err(Errno::ENOENT) => {
+                let flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_CLOEXEC;
+                fcntl::open(*arg, flags, FILE_MODE).and_then(unistd::close)
+            }
+            _ => Ok(()),
+        };
+        let flag = stat::UtimensatFlags::FollowSymlink;
+        let res =
+            res.and_then(|_| stat::utimensat(None, *arg, &timespec(atime), &timespec(mtime), flag));
+        if let Err(err) = res {
+            eprintln!("Error: {}: {}", arg, err);
+            status = err as i32;
+        }
+    }
+    Ok(Status::from_code(status))
+}
+
+// ********** file.truncate **********
+
+const FILE_TRUNCATE: Spec = Spec::new(
+    "[+|-]SIZE[KMG] FILE...",
+    2,
+    MANY,
+    &[Opt::flag('c', "no-create", "Do not create files")],
+);
+
+// absolute or relative (+N, -N) size with an optional unit
+fn parse_size(spec: &str) -> Option<(Option<bool>, i64)> {
+    let (rel, num) = match spec.as_bytes().first() {
+        Some(b'+') => (Some(true), &spec[1..]),
+        Some(b'-') => (Some(false), &spec[1..]),
+        _ => (None, spec),
+    };
+    let (num, unit) = match num.char_indices().last() {
+        Some((pos, 'K')) => (&num[..pos], 1 << 10),
+        Some((pos, 'M')) => (&num[..pos], 1 << 20),
+        Some((pos, 'G')) => (&num[..pos], 1 << 30),
+        _ => (num, 1),
+    };
+    let size = num.parse::<i64>().ok()?.checked_mul(unit)?;
+    Some((rel, size))
+}
+
+pub fn do_file_truncate(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_TRUNCATE, args);
+    let (rel, size) = match parse_size(opts.args[0]) {
+        Some(size) => size,
+        None => {
+            return Ok(usage_error(
+                args[0],
+                &format!("invalid size '{}'", opts.args[0]),
+            ))
+        }
+    };
+    let mut flags = OFlag::O_WRONLY | OFlag::O_CLOEXEC;
+    if !opts.has("no-create") {
+        flags |= OFlag::O_CREAT;
+    }
+    let mut status = 0;
+    for arg in &opts.args[1..] {
+        let res = match fcntl::open(*arg, flags, FILE_MODE) {
+            Err(Errno::ENOENT) if opts.has("no-create") => continue,
+            Err(err) => Err(err),
+            Ok(fd) => {
+                let res = stat::fstat(fd).and_then(|st| {
+                    let len = match rel {
+                        Some(true) => st.st_size + size,
+                        Some(false) => (st.st_size - size).max(0),
+                        None => size,
+                    };
+                    unistd::ftruncate(fd, len)
+                });
+                unistd::close(fd)?;
+                res
+            }
+        };
+        if let Err(err) = res {
+            eprintln!("Error: {}: {}", arg, err);
+            status = err as i32;
+        }
+    }
+    Ok(Status::from_code(status))
+}
+
+// ********** file.temp **********
+
+const TEMP_TEMPLATE: &str = "frish.XXXXXX";
+
+const FILE_TEMP: Spec = Spec::new(
+    "[TEMPLATE]",
+    0,
+    1,
+    &[
+        Opt::value(
+            'p',
+            "tmpdir",
+            "DIR",
+            "Create it in DIR instead of $TMPDIR or /tmp",
+        ),
+        Opt::long("cleanup", None, "Remove it when the shell exits"),
+    ],
+);
+
+/// Template of a temporary file or directory ending with XXXXXX.
+/// A given template is relative to the current directory unless `-p DIR` is used.
+pub fn temp_template(state: &State, opts: &Opts) -> PathBuf {
+    match (opts.value("tmpdir"), opts.args.first()) {
+        (Some(dir), template) => Path::new(dir).join(template.unwrap_or(&TEMP_TEMPLATE)),
+        (None, Some(template)) => PathBuf::from(template),
+        (None, None) => {
+            let tmpdir = state
+                .get_var("TMPDIR")
+                .filter(|dir| !dir.is_empty())
+                .unwrap_or_else(|| String::from("/tmp"));
+            Path::new(&tmpdir).join(TEMP_TEMPLATE)
+        }
+    }
+}
+
+pub fn do_file_temp(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, FILE_TEMP, args);
+    let (fd, path) = unistd::mkstemp(&temp_template(state, &opts))?;
+    unistd::close(fd)?;
+    if opts.has("cleanup") {
+        state.add_cleanup(&path);
+    }
+    println!("{}", path.display());
+    Ok(Status::success())
+}
+
+// ********** umask **********
+
+const UMASK: Spec = Spec::new(
+    "[MODE]",
+    0,
+    1,
+    &[Opt::flag(
+        'S',
+        "symbolic",
+        "Print the mask in symbolic form",
+    )],
+);
+
+// permissions allowed by the mask, e.g. u=rwx,g=rx,o=rx
+fn symbolic_umask(mask: u32) -> String {
+    let allowed = !mask & 0o777;
+    let class = |shift: u32| {
+        let bits = (allowed >> shift) & 0o7;
+        [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')]
+            .iter()
+            .filter(|(bit, _)| bits & bit != 0)
+            .map(|(_, ch)| ch)
+            .collect::<String>()
+    };
+    format!("u={},g={},o={}", class(6), class(3), class(0))
+}
+
+pub fn do_umask(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, UMASK, args);
+    let mask = state.umask.get();
+    match opts.args.first() {
+        None if opts.has("symbolic") => println!("{}", symbolic_umask(mask)),
+        None => println!("{:04o}", mask),
+        // octal is the mask itself, symbolic modes give the allowed permissions
+        Some(spec) if spec.chars().all(|ch| ch.is_digit(8)) => match parse_mode(spec, 0, 0, true) {
+            Ok(mask) if mask <= 0o777 => state.set_umask(mask),
+            _ => return Ok(usage_error(args[0], &format!("invalid mask '{}'", spec))),
+        },
+        Some(spec) => match parse_mode(spec, !mask & 0o777, 0, true) {
+            Ok(allowed) => state.set_umask(!allowed & 0o777),
+            Err(msg) => return Ok(usage_error(args[0], &msg)),
+        },
     }
     Ok(Status::success())
 }
@@ -1,20 +1,32 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io;
 
 use crate::state::{State, Status};
 
+#[macro_use]
+mod opts;
+
 mod base;
 mod dir;
 mod file;
+pub mod plugin;
 mod process;
 
-type BuiltinHandler = fn(&State, &[&str]) -> io::Result<Status>;
+/// Handler of a builtin, it gets the command name and its arguments.
+pub type BuiltinHandler = fn(&State, &[&str]) -> io::Result<Status>;
+
+/// Code of a builtin, in the shell or in a loaded plugin.
+#[derive(Clone, Copy)]
+pub(crate) enum Handler {
+    Native(BuiltinHandler),
+    Plugin(plugin::Handler),
+}
 
 #[derive(Clone)]
 pub struct Builtin<'a> {
     pub command: &'a str,
-    pub handler: BuiltinHandler,
+    pub(crate) handler: Handler,
     pub hint: &'a str,
 }
 
@@ -22,10 +34,18 @@ impl<'a> Builtin<'a> {
     pub fn new(command: &'a str, handler: BuiltinHandler, hint: &'a str) -> Self {
         Builtin {
             command,
-            handler,
+            handler: Handler::Native(handler),
             hint,
         }
     }
+
+    /// Run the builtin with the arguments, the first one is the command name.
+    pub fn run(&self, state: &State, args: &[&str]) -> io::Result<Status> {
+        match self.handler {
+            Handler::Native(handler) => handler(state, args),
+            Handler::Plugin(handler) => plugin::run(handler, args),
+        }
+    }
 }
 
 impl<'a> fmt::Debug for Builtin<'a> {
@@ -41,6 +61,13 @@ impl<'a> fmt::Debug for Builtin<'a> {
 pub struct Builtins<'a> {
     items: Vec<Builtin<'a>>,
     hash: HashMap<&'a str, usize>,
+    disabled: HashSet<&'a str>,
+}
+
+impl<'a> Default for Builtins<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> Builtins<'a> {
@@ -50,12 +77,77 @@ impl<'a> Builtins<'a> {
         for (i, b) in items.iter().enumerate() {
             hash.insert(b.command, i);
         }
-        Builtins { items, hash }
+        Builtins {
+            items,
+            hash,
+            disabled: HashSet::new(),
+        }
     }
 
-    pub fn find(&self, name: &str) -> Option<&Builtin> {
+    /// The enabled builtin of the name.
+    pub fn find(&self, name: &str) -> Option<&Builtin<'a>> {
+        self.hash
+            .get(name)
+            .map(|&idx| &self.items[idx])
+            .filter(|builtin| !self.disabled.contains(builtin.command))
+    }
+
+    /// The builtin of the name, also a disabled one.
+    pub fn get(&self, name: &str) -> Option<&Builtin<'a>> {
         self.hash.get(name).map(|&idx| &self.items[idx])
     }
+
+    /// The enabled builtins.
+    pub fn iter(&self) -> impl Iterator<Item = &Builtin<'a>> {
+        self.items
+            .iter()
+            .filter(|builtin| !self.disabled.contains(builtin.command))
+    }
+
+    /// All the builtins, each one with the flag whether it is enabled.
+    pub fn all(&self) -> impl Iterator<Item = (&Builtin<'a>, bool)> {
+        self.items
+            .iter()
+            .map(|builtin| (builtin, !self.disabled.contains(builtin.command)))
+    }
+
+    /// Enable or disable the builtin, a disabled one is not found and the command
+    /// of its name runs an external program. False if there is no such builtin.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let command = match self.hash.get(name) {
+            Some(&idx) => self.items[idx].command,
+            None => return false,
+        };
+        if enabled {
+            self.disabled.remove(command);
+        } else {
+            self.disabled.insert(command);
+        }
+        true
+    }
+
+    /// Add the builtin, it replaces the builtin of the same name which is returned.
+    pub fn register(&mut self, builtin: Builtin<'a>) -> Option<Builtin<'a>> {
+        match self.hash.get(builtin.command) {
+            Some(&idx) => Some(std::mem::replace(&mut self.items[idx], builtin)),
+            None => {
+                self.hash.insert(builtin.command, self.items.len());
+                self.items.push(builtin);
+                None
+            }
+        }
+    }
+
+    /// Remove the builtin of the name and return it.
+    pub fn unregister(&mut self, name: &str) -> Option<Builtin<'a>> {
+        let idx = self.hash.remove(name)?;
+        let builtin = self.items.remove(idx);
+        self.disabled.remove(builtin.command);
+        for idx in self.hash.values_mut().filter(|i| **i > idx) {
+            *idx -= 1;
+        }
+        Some(builtin)
+    }
 }
 
 // ********** default builtins **********
@@ -73,17 +165,46 @@ fn default_builtins<'a>() -> Vec<Builtin<'a>> {
     vec![
         // base
         builtin("help", do_help, "Print short help for all builtin commands"),
+        builtin("enable", do_enable, "Enable, disable or load builtins"),
+        builtin("disable", do_disable, "Disable builtins"),
         builtin("name", do_name, "Print or change the shell name"),
         builtin("loglevel", do_loglevel, "Print or change logging level"),
+        builtin("set", do_set, "Print or change the shell options"),
+        builtin("history", do_history, "Print or edit the command history"),
+        builtin(
+            "complete",
+            do_complete,
+            "Set completers of command arguments",
+        ),
         builtin("print", do_print, "Print its arguments"),
         builtin("echo", do_echo, "Print its arguments and the newline"),
         // dir
         builtin("dir.change", do_dir_change, "Change the current directory"),
         builtin("dir.where", do_dir_where, "Print current working directory"),
+        builtin(
+            "dir.push",
+            do_dir_push,
+            "Push directory to the stack and change to it",
+        ),
+        builtin(
+            "dir.pop",
+            do_dir_pop,
+            "Pop directory from the stack and change to it",
+        ),
+        builtin("dir.stack", do_dir_stack, "Print the directory stack"),
         builtin("dir.make", do_dir_make, "Make directories"),
         builtin("dir.remove", do_dir_remove, "Remove directories"),
         builtin("dir.list", do_dir_list, "List directory"),
         builtin("dir.inspect", do_dir_inspect, "Inspect directory"),
+        builtin("dir.tree", do_dir_tree, "Print directory tree"),
+        builtin("dir.copy", do_dir_copy, "Copy directory"),
+        builtin("dir.size", do_dir_size, "Print disk usage of directories"),
+        builtin(
+            "dir.find",
+            do_dir_find,
+            "Search for files in a directory tree",
+        ),
+        builtin("dir.temp", do_dir_temp, "Create a temporary directory"),
         // file
         builtin("link.hard", do_link_hard, "Create hard link"),
         builtin("link.soft", do_link_soft, "Create symbolic/soft link"),
@@ -91,6 +212,33 @@ fn default_builtins<'a>() -> Vec<Builtin<'a>> {
         builtin("unlink", do_unlink, "Unlink files"),
         builtin("rename", do_rename, "Rename file"),
         builtin("cpcat", do_cpcat, "Copy file"),
+        builtin("file.head", do_file_head, "Print the first lines of files"),
+        builtin("file.tail", do_file_tail, "Print the last lines of files"),
+        builtin(
+            "file.count",
+            do_file_count,
+            "Print line, word and byte counts",
+        ),
+        builtin("file.hash", do_file_hash, "Print checksums of files"),
+        builtin("file.temp", do_file_temp, "Create a temporary file"),
+        builtin("file.stat", do_file_stat, "Print file status"),
+        builtin("file.chmod", do_file_chmod, "Change file mode"),
+        builtin("file.chown", do_file_chown, "Change file owner and group"),
+        builtin(
+            "file.touch",
+            do_file_touch,
+            "Create files or change their times",
+        ),
+        builtin(
+            "file.truncate",
+            do_file_truncate,
+            "Shrink or extend files to the size",
+        ),
+        builtin(
+            "umask",
+            do_umask,
+            "Print or change the file mode creation mask",
+        ),
         // process
         builtin("pid", do_pid, "Print PID of the current shell"),
         builtin("ppid", do_ppid, "Print PPID of the current shell"),
@@ -104,5 +252,28 @@ fn default_builtins<'a>() -> Vec<Builtin<'a>> {
         builtin("depth", do_depth, "Print the depth of the current subshell"),
         builtin("subshell", do_subshell, "Run a subshell with a command"),
         builtin("pipes", do_pipes, "Create a pipeline"),
+        builtin("type", do_type, "Describe how the commands would run"),
+        builtin("which", do_which, "Print the paths of the commands"),
+        builtin(
+            "command",
+            do_command,
+            "Run or describe a builtin or an external command",
+        ),
+        builtin("builtin", do_builtin, "Run a builtin, also a disabled one"),
+        builtin(
+            "exec",
+            do_exec,
+            "Replace the shell by a command or redirect the shell",
+        ),
+        builtin(
+            "hash",
+            do_hash,
+            "Print or change the hash table of command paths",
+        ),
+        builtin(
+            "trap",
+            do_trap,
+            "Run a command on signals, on exit (EXIT) or on failures (ERR)",
+        ),
     ]
 }
@@ -0,0 +1,245 @@
+use crate::state::{State, Status};
+
+/// Upper bound for the number of operands meaning "no limit".
+pub const MANY: usize = usize::MAX;
+
+/// Status returned by builtins on invalid usage.
+pub const USAGE_STATUS: i32 = 2;
+
+// ********** option specification **********
+
+#[derive(Debug)]
+pub struct Opt {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Option<&'static str>,
+    pub hint: &'static str,
+}
+
+impl Opt {
+    /// Option without a value, e.g. `-p` or `--parents`.
+    pub const fn flag(short: char, long: &'static str, hint: &'static str) -> Opt {
+        Opt {
+            short: Some(short),
+            long,
+            value: None,
+            hint,
+        }
+    }
+
+    /// Option taking a value, e.g. `-n 10`, `-n10`, `--lines 10` or `--lines=10`.
+    pub const fn value(
+        short: char,
+        long: &'static str,
+        value: &'static str,
+        hint: &'static str,
+    ) -> Opt {
+        Opt {
+            short: Some(short),
+            long,
+            value: Some(value),
+            hint,
+        }
+    }
+
+    /// Option with a long name only.
+    pub const fn long(long: &'static str, value: Option<&'static str>, hint: &'static str) -> Opt {
+        Opt {
+            short: None,
+            long,
+            value,
+            hint,
+        }
+    }
+}
+
+/// Usage of a builtin: its options and the allowed number of operands.
+#[derive(Debug)]
+pub struct Spec {
+    pub operands: &'static str,
+    pub min: usize,
+    pub max: usize,
+    pub opts: &'static [Opt],
+}
+
+impl Spec {
+    pub const fn new(operands: &'static str, min: usize, max: usize, opts: &'static [Opt]) -> Spec {
+        Spec {
+            operands,
+            min,
+            max,
+            opts,
+        }
+    }
+
+    fn find_short(&self, ch: char) -> Option<&Opt> {
+        self.opts.iter().find(|opt| opt.short == Some(ch))
+    }
+
+    fn find_long(&self, name: &str) -> Option<&Opt> {
+        self.opts.iter().find(|opt| opt.long == name)
+    }
+}
+
+// ********** parsed options **********
+
+#[derive(Debug)]
+pub struct Opts<'a> {
+    given: Vec<(&'static str, Option<&'a str>)>,
+    pub args: Vec<&'a str>,
+}
+
+impl<'a> Opts<'a> {
+    /// Is the option (given by its long name) present?
+    pub fn has(&self, long: &str) -> bool {
+        self.given.iter().any(|(name, _)| *name == long)
+    }
+
+    /// The last value of the option.
+    pub fn value(&self, long: &str) -> Option<&'a str> {
+        self.given
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == long)
+            .and_then(|(_, value)| *value)
+    }
+
+    /// The last value of the option parsed as a number.
+    pub fn number<T: std::str::FromStr>(&self, long: &str) -> Result<Option<T>, String> {
+        match self.value(long) {
+            Some(value) => match value.parse() {
+                Ok(number) => Ok(Some(number)),
+                Err(_) => Err(format!(
+                    "invalid number '{}' for option '--{}'",
+                    value, long
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+// ********** parsing **********
+
+fn is_operand(arg: &str) -> bool {
+    // a lone dash usually stands for stdin/stdout, negative numbers are operands too
+    arg == "-" || !arg.starts_with('-') || arg[1..].starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+/// Parse the options of a builtin, `args[0]` is the command name.
+/// Parsing stops at `--` or at the first operand, `--help` is always accepted.
+pub fn parse<'a>(spec: &Spec, args: &[&'a str]) -> Result<Opts<'a>, String> {
+    let mut given = Vec::new();
+    let mut iter = args.iter().skip(1).peekable();
+    while let Some(&&arg) = iter.peek() {
+        if arg == "--" {
+            iter.next();
+            break;
+        }
+        if is_operand(arg) {
+            break;
+        }
+        iter.next();
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            if name == "help" && inline.is_none() {
+                given.push(("help", None));
+                continue;
+            }
+            let opt = spec
+                .find_long(name)
+                .ok_or_else(|| format!("unrecognized option '--{}'", name))?;
+            let value = match (opt.value, inline) {
+                (None, None) => None,
+                (None, Some(_)) => return Err(format!("option '--{}' takes no value", name)),
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(
+                    *iter
+                        .next()
+                        .ok_or_else(|| format!("option '--{}' requires a value", name))?,
+                ),
+            };
+            given.push((opt.long, value));
+        } else {
+            // cluster of short options, e.g. -pv or -n10
+            for (pos, ch) in arg.char_indices().skip(1) {
+                let opt = spec
+                    .find_short(ch)
+                    .ok_or_else(|| format!("invalid option -- '{}'", ch))?;
+                if opt.value.is_none() {
+                    given.push((opt.long, None));
+                    continue;
+                }
+                let rest = &arg[pos + ch.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest
+                } else {
+                    iter.next()
+                        .ok_or_else(|| format!("option requires a value -- '{}'", ch))?
+                };
+                given.push((opt.long, Some(value)));
+                break;
+            }
+        }
+    }
+    let args: Vec<&str> = iter.copied().collect();
+    if given.iter().any(|(name, _)| *name == "help") {
+        return Ok(Opts { given, args });
+    }
+    if args.len() < spec.min {
+        return Err(String::from("missing operand"));
+    }
+    if args.len() > spec.max {
+        return Err(format!("extra operand '{}'", args[spec.max]));
+    }
+    Ok(Opts { given, args })
+}
+
+pub fn print_help(state: &State, spec: &Spec, command: &str) {
+    println!("Usage: {} [OPTION]... {}", command, spec.operands);
+    if let Some(builtin) = state.builtins.borrow().find(command) {
+        println!("{}", builtin.hint);
+    }
+    println!();
+    for opt in spec
+        .opts
+        .iter()
+        .chain(&[Opt::long("help", None, "Print this help")])
+    {
+        let short = match opt.short {
+            Some(ch) => format!("-{},", ch),
+            None => String::new(),
+        };
+        let long = match opt.value {
+            Some(value) => format!("--{} {}", opt.long, value),
+            None => format!("--{}", opt.long),
+        };
+        println!("  {:4}{:20}{}", short, long, opt.hint);
+    }
+}
+
+/// Report invalid usage of a builtin and return the status for it.
+pub fn usage_error(command: &str, msg: &str) -> Status {
+    eprintln!("{}: {}", command, msg);
+    eprintln!("Try '{} --help' for more information.", command);
+    Status::from_code(USAGE_STATUS)
+}
+
+/// Parse the options of a builtin or return from the handler.
+/// Prints the help on `--help` and reports usage errors.
+macro_rules! parse_opts {
+    ($state:expr, $spec:expr, $args:expr) => {{
+        let args: &[&str] = $args;
+        match $crate::builtins::opts::parse(&$spec, args) {
+            Ok(opts) if opts.has("help") => {
+                $crate::builtins::opts::print_help($state, &$spec, args[0]);
+                return Ok($crate::state::Status::success());
+            }
+            Ok(opts) => opts,
+            Err(msg) => return Ok($crate::builtins::opts::usage_error(args[0], &msg)),
+        }
+    }};
+}
@@ -0,0 +1,126 @@
+// Builtins loaded from shared objects through a versioned C interface.
+//
+// A plugin exports the function `frish_plugin_init` returning the description
+// of its builtins, see plugin/frish_plugin.h. The layout of the structures
+// below must follow the header of the version ABI_VERSION.
+
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use crate::builtins::{Builtin, Handler as BuiltinHandler};
+use crate::state::Status;
+
+/// Version of the interface, plugins of other versions are refused.
+pub const ABI_VERSION: u32 = 1;
+
+/// Function of a plugin builtin, it gets the arguments (with the command name
+/// first) terminated by a null pointer and returns the status.
+pub type Handler = unsafe extern "C" fn(argc: c_int, argv: *const *const c_char) -> c_int;
+
+#[repr(C)]
+struct RawBuiltin {
+    name: *const c_char,
+    hint: *const c_char,
+    handler: Option<Handler>,
+}
+
+#[repr(C)]
+struct RawPlugin {
+    abi_version: u32,
+    count: usize,
+    builtins: *const RawBuiltin,
+}
+
+type Init = unsafe extern "C" fn() -> *const RawPlugin;
+
+fn plugin_error(path: &str, msg: &str) -> io::Error {
+    io::Error::other(format!("{}: {}", path, msg))
+}
+
+fn last_dl_error(path: &str) -> io::Error {
+    let msg = unsafe { libc::dlerror() };
+    if msg.is_null() {
+        return plugin_error(path, "cannot load the plugin");
+    }
+    let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+    io::Error::other(msg.into_owned())
+}
+
+// the string of the loaded library, it is never unloaded
+fn static_str(path: &str, ptr: *const c_char) -> io::Result<&'static str> {
+    if ptr.is_null() {
+        return Err(plugin_error(path, "builtin without a name or a hint"));
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|_| plugin_error(path, "builtin name or hint is not UTF-8"))
+}
+
+fn builtins(path: &str, plugin: &RawPlugin) -> io::Result<Vec<Builtin<'static>>> {
+    if plugin.abi_version != ABI_VERSION {
+        let msg = format!(
+            "unsupported plugin ABI version {} (expected {})",
+            plugin.abi_version, ABI_VERSION
+        );
+        return Err(plugin_error(path, &msg));
+    }
+    if plugin.count > 0 && plugin.builtins.is_null() {
+        return Err(plugin_error(path, "no builtins"));
+    }
+    let mut builtins = Vec::with_capacity(plugin.count);
+    for i in 0..plugin.count {
+        let raw = unsafe { &*plugin.builtins.add(i) };
+        let handler = raw
+            .handler
+            .ok_or_else(|| plugin_error(path, "builtin without a handler"))?;
+        builtins.push(Builtin {
+            command: static_str(path, raw.name)?,
+            handler: BuiltinHandler::Plugin(handler),
+            hint: static_str(path, raw.hint)?,
+        });
+    }
+    Ok(builtins)
+}
+
+/// Load the shared object and return its builtins. A path without a slash is
+/// searched for by the dynamic linker. The library stays loaded for good since
+/// the builtins refer to its code and strings.
+pub fn load(path: &str) -> io::Result<Vec<Builtin<'static>>> {
+    let cpath = CString::new(path).map_err(|_| plugin_error(path, "invalid path"))?;
+    let handle = unsafe { libc::dlopen(cpath.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(last_dl_error(path));
+    }
+    let init = unsafe { libc::dlsym(handle, c"frish_plugin_init".as_ptr()) };
+    let result = if init.is_null() {
+        Err(plugin_error(path, "not a frish plugin"))
+    } else {
+        let init: Init = unsafe { std::mem::transmute::<*mut libc::c_void, Init>(init) };
+        match unsafe { init().as_ref() } {
+            Some(plugin) => builtins(path, plugin),
+            None => Err(plugin_error(path, "plugin initialization failed")),
+        }
+    };
+    if result.is_err() {
+        unsafe { libc::dlclose(handle) };
+    }
+    result
+}
+
+/// Run the builtin of a plugin with the arguments.
+pub fn run(handler: Handler, args: &[&str]) -> io::Result<Status> {
+    let args = args
+        .iter()
+        .map(|&arg| CString::new(arg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(ptr::null());
+    // the plugin writes through the C library which has buffers of its own
+    io::stdout().flush()?;
+    let code = unsafe { handler(args.len() as c_int, argv.as_ptr()) };
+    unsafe { libc::fflush(ptr::null_mut()) };
+    Ok(Status::from_code(code))
+}
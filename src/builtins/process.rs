@@ -1,10 +1,17 @@
+use nix::sys::signal::Signal;
 use nix::unistd;
 use std::io;
 
+use crate::builtins::opts::{usage_error, Opt, Spec, MANY};
+use crate::common::Command;
+use crate::parser::quote;
+use crate::shell::options::ShellOption;
 use crate::shell::{
     eval::{eval, read_eval_loop},
-    exec::wait_process,
+    exec::{exec_command, fork_child_wait, run_command, wait_process},
     pipes::{pipes_begin, pipes_cont, pipes_end},
+    resolve,
+    trap::{self, Condition},
 };
 use crate::state::{State, Status};
 
@@ -44,22 +51,333 @@ pub fn do_depth(state: &State, _: &[&str]) -> io::Result<Status> {
 }
 
 pub fn do_subshell(state: &State, args: &[&str]) -> io::Result<Status> {
-    let state = state.sub();
+    let substate = state.sub();
     if args.len() > 1 {
-        eval(&state, args[1]);
-    } else {
-        read_eval_loop(&state);
+        // a child process like ( COMMANDS )
+        return fork_child_wait(&mut || {
+            eval(&substate, args[1]);
+            trap::run_exit(&substate);
+            substate.run_cleanup();
+            std::process::exit(substate.status.get().code());
+        });
     }
-    Ok(Status::from(&state.status.get()))
+    // the subshell reads the input of the shell in the same process
+    read_eval_loop(&substate);
+    trap::run_exit(&substate);
+    substate.run_cleanup();
+    state.set_umask(state.umask.get());
+    state.restore_environment();
+    Ok(Status::from(&substate.status.get()))
 }
 
+// The status of the last command, with pipefail the one of the last failed command.
 pub fn do_pipes(state: &State, args: &[&str]) -> io::Result<Status> {
-    let mut fds2 = pipes_begin(state, args[1])?;
-    for i in 2..args.len() - 1 {
+    if args.len() < 3 {
+        return Ok(usage_error(args[0], "at least two commands expected"));
+    }
+    let (mut fds2, pid) = pipes_begin(state, args[1])?;
+    let mut pids = vec![pid];
+    for arg in &args[2..args.len() - 1] {
         let fds1 = fds2;
-        fds2 = pipes_cont(state, args[i], fds1)?;
+        let (fds, pid) = pipes_cont(state, arg, fds1)?;
+        fds2 = fds;
+        pids.push(pid);
     }
-    let pid = pipes_end(state, args[args.len() - 1], fds2)?;
-    wait_process(pid)?;
-    Ok(Status::success())
+    pids.push(pipes_end(state, args[args.len() - 1], fds2)?);
+    let mut status = Status::success();
+    for pid in pids {
+        let last = wait_process(pid)?;
+        if last.code() != 0 || !state.option(ShellOption::Pipefail) {
+            status = last;
+        }
+    }
+    Ok(status)
+}
+
+// how the command would run, None if it is not found
+fn describe(state: &State, name: &str) -> Option<String> {
+    if state.builtins.borrow().find(name).is_some() {
+        return Some(format!("{} is a shell builtin", name));
+    }
+    if let Some(path) = resolve::hashed(state, name) {
+        return Some(format!("{} is hashed ({})", name, path.display()));
+    }
+    match resolve::find_file(state, name, false) {
+        Some(path) if resolve::is_executable(&path) => {
+            Some(format!("{} is {}", name, path.display()))
+        }
+        _ => None,
+    }
+}
+
+const TYPE: Spec = Spec::new(
+    "NAME...",
+    1,
+    MANY,
+    &[
+        Opt::flag('a', "all", "Print all builtins and files of the names"),
+        Opt::flag('t', "type", "Print only 'builtin' or 'file'"),
+    ],
+);
+
+pub fn do_type(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, TYPE, args);
+    let mut status = Status::success();
+    for name in &opts.args {
+        let builtin = state.builtins.borrow().find(name).is_some();
+        let mut found = Vec::new();
+        if opts.has("all") {
+            if builtin {
+                found.push(("builtin", format!("{} is a shell builtin", name)));
+            }
+            for path in resolve::search_path(state, name) {
+                found.push(("file", format!("{} is {}", name, path.display())));
+            }
+        } else if let Some(text) = describe(state, name) {
+            found.push((if builtin { "builtin" } else { "file" }, text));
+        }
+        if found.is_empty() {
+            eprintln!("Error: {}: not found", name);
+            status = Status::from_code(1);
+        }
+        for (kind, text) in found {
+            println!("{}", if opts.has("type") { kind } else { &text });
+        }
+    }
+    Ok(status)
+}
+
+const WHICH: Spec = Spec::new(
+    "NAME...",
+    1,
+    MANY,
+    &[Opt::flag('a', "all", "Print all matching files on PATH")],
+);
+
+pub fn do_which(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, WHICH, args);
+    let mut status = Status::success();
+    for name in &opts.args {
+        let paths = match resolve::find_file(state, name, false) {
+            Some(path) if name.contains('/') => vec![path],
+            _ if opts.has("all") => resolve::search_path(state, name),
+            path => path.into_iter().collect(),
+        };
+        let paths: Vec<_> = paths
+            .into_iter()
+            .filter(|path| resolve::is_executable(path))
+            .collect();
+        if paths.is_empty() {
+            status = Status::from_code(1);
+        }
+        for path in paths {
+            println!("{}", path.display());
+        }
+    }
+    Ok(status)
+}
+
+const COMMAND: Spec = Spec::new(
+    "NAME [ARG]...",
+    1,
+    MANY,
+    &[
+        Opt::flag(
+            'v',
+            "path",
+            "Print the builtin name or the path of the command",
+        ),
+        Opt::flag('V', "describe", "Print how the command would run"),
+    ],
+);
+
+pub fn do_command(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, COMMAND, args);
+    let name = opts.args[0];
+    if opts.has("path") || opts.has("describe") {
+        let text = if opts.has("describe") {
+            describe(state, name)
+        } else if state.builtins.borrow().find(name).is_some() {
+            Some(String::from(name))
+        } else {
+            resolve::find_file(state, name, false)
+                .filter(|path| resolve::is_executable(path))
+                .map(|path| path.display().to_string())
+        };
+        return match text {
+            Some(text) => {
+                println!("{}", text);
+                Ok(Status::success())
+            }
+            None => {
+                if opts.has("describe") {
+                    eprintln!("Error: {}: not found", name);
+                }
+                Ok(Status::from_code(1))
+            }
+        };
+    }
+    // the redirections and the background were handled for this builtin
+    let cmd = Command {
+        args: opts.args,
+        background: false,
+        redirects: Vec::new(),
+    };
+    run_command(state, &cmd)
+}
+
+const BUILTIN: Spec = Spec::new("NAME [ARG]...", 1, MANY, &[]);
+
+pub fn do_builtin(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, BUILTIN, args);
+    let builtin = state.builtins.borrow().get(opts.args[0]).cloned();
+    match builtin {
+        Some(builtin) => builtin.run(state, &opts.args),
+        None => {
+            eprintln!("Error: {}: not a shell builtin", opts.args[0]);
+            Ok(Status::from_code(1))
+        }
+    }
+}
+
+const HASH: Spec = Spec::new(
+    "[NAME]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('r', "reset", "Forget all remembered paths"),
+        Opt::flag('d', "delete", "Forget the paths of the names"),
+        Opt::flag('t', "print", "Print the remembered paths of the names"),
+    ],
+);
+
+pub fn do_hash(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, HASH, args);
+    if opts.has("reset") {
+        state.hash.borrow_mut().clear();
+    }
+    let mut status = Status::success();
+    for name in &opts.args {
+        let found = if opts.has("delete") {
+            state.hash.borrow_mut().remove(*name).is_some()
+        } else if opts.has("print") {
+            resolve::hashed(state, name)
+                .map(|path| println!("{}", path.display()))
+                .is_some()
+        } else {
+            // builtins are not looked up
+            state.builtins.borrow().find(name).is_some() || resolve::hash(state, name).is_some()
+        };
+        if !found {
+            eprintln!("Error: {}: not found", name);
+            status = Status::from_code(1);
+        }
+    }
+    if opts.args.is_empty() && !opts.has("reset") {
+        let hash = state.hash.borrow();
+        let mut names: Vec<_> = hash.keys().collect();
+        names.sort();
+        if !names.is_empty() {
+            println!("hits\tcommand");
+        }
+        for name in names {
+            println!("{:4}\t{}", hash[name].hits, hash[name].path.display());
+        }
+    }
+    Ok(status)
+}
+
+const EXEC: Spec = Spec::new("[COMMAND [ARG]...]", 0, MANY, &[]);
+
+pub fn do_exec(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, EXEC, args);
+    if opts.args.is_empty() {
+        // the redirections change the descriptors of the shell
+        state.keep_redirects.set(true);
+        return Ok(Status::success());
+    }
+    let cmd = Command {
+        args: opts.args,
+        background: false,
+        redirects: Vec::new(),
+    };
+    let status = exec_command(state, &cmd);
+    // a script does not go on after a failed exec
+    if !state.interactive {
+        state.terminate();
+    }
+    Ok(status)
+}
+
+const TRAP: Spec = Spec::new(
+    "[COMMAND] [CONDITION]...",
+    0,
+    MANY,
+    &[
+        Opt::flag('l', "list", "Print the signal names with their numbers"),
+        Opt::flag('p', "print", "Print the traps (of the conditions)"),
+    ],
+);
+
+// the conditions of the names, the invalid ones are reported
+fn conditions(names: &[&str]) -> Result<Vec<Condition>, Status> {
+    names
+        .iter()
+        .map(|name| name.parse())
+        .collect::<Result<_, String>>()
+        .map_err(|msg| {
+            eprintln!("Error: {}", msg);
+            Status::from_code(1)
+        })
+}
+
+pub fn do_trap(state: &State, args: &[&str]) -> io::Result<Status> {
+    let opts = parse_opts!(state, TRAP, args);
+    if opts.has("list") {
+        for sig in Signal::iterator() {
+            println!("{:2}) {}", sig as i32, sig);
+        }
+        return Ok(Status::success());
+    }
+    if opts.has("print") || opts.args.is_empty() {
+        let conds = match conditions(&opts.args) {
+            Ok(conds) => conds,
+            Err(status) => return Ok(status),
+        };
+        for (cond, command) in state.traps.borrow().iter() {
+            if conds.is_empty() || conds.contains(cond) {
+                println!("trap -- {} {}", quote(command), cond);
+            }
+        }
+        return Ok(Status::success());
+    }
+    // a lone condition is reset like with `-`
+    let (command, names) = match opts.args[0].parse::<Condition>() {
+        Ok(_) if opts.args.len() == 1 => ("-", &opts.args[..]),
+        _ if opts.args.len() == 1 => {
+            return Ok(usage_error(args[0], "missing condition"));
+        }
+        _ => (opts.args[0], &opts.args[1..]),
+    };
+    let conds = match conditions(names) {
+        Ok(conds) => conds,
+        Err(status) => return Ok(status),
+    };
+    let mut status = Status::success();
+    for cond in conds {
+        let command = if command == "-" { None } else { Some(command) };
+        if let Condition::Signal(sig) = cond {
+            if let Err(err) = trap::set_signal(sig, command) {
+                eprintln!("Error: {}: {}", cond, err.desc());
+                status = Status::from_code(1);
+                continue;
+            }
+        }
+        let mut traps = state.traps.borrow_mut();
+        match command {
+            Some(command) => traps.insert(cond, String::from(command)),
+            None => traps.remove(&cond),
+        };
+    }
+    Ok(status)
 }
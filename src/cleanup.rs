@@ -0,0 +1,174 @@
+// Removal of temporary paths when the shell exits, also on fatal signals.
+//
+// A signal handler may only call async-signal-safe functions, so the paths
+// are kept as C strings in a registry that is replaced as a whole on every
+// change, and they are removed with plain system calls.
+
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::Once;
+
+// paths with the PID of the process which registered them,
+// a forked child must not remove the paths of its parent
+type Registry = Vec<(i32, CString)>;
+
+static REGISTRY: AtomicPtr<Registry> = AtomicPtr::new(ptr::null_mut());
+
+// the handlers are installed by the first registered path
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+const FATAL_SIGNALS: [Signal; 8] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGPIPE,
+    Signal::SIGALRM,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+// deeper trees are left behind rather than risking the stack in a handler
+const MAX_DEPTH: usize = 128;
+
+// the shell is single-threaded, so a handler sees either the old or the new registry
+fn update<F: FnOnce(&mut Registry)>(change: F) {
+    let old = REGISTRY.load(Ordering::SeqCst);
+    let mut registry = if old.is_null() {
+        Vec::new()
+    } else {
+        unsafe { (*old).clone() }
+    };
+    change(&mut registry);
+    REGISTRY.store(Box::into_raw(Box::new(registry)), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+/// Register an absolute path to be removed by `remove` or on a fatal signal.
+pub fn register(path: &Path) {
+    static HANDLERS: Once = Once::new();
+    HANDLERS.call_once(install_handlers);
+    if let Ok(path) = CString::new(path.as_os_str().as_bytes()) {
+        let pid = unistd::getpid().as_raw();
+        update(|registry| registry.push((pid, path)));
+    }
+}
+
+/// Remove the registered path (recursively) if this process registered it.
+pub fn remove(path: &Path) {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let pid = unistd::getpid().as_raw();
+    let mut owned = false;
+    update(|registry| {
+        registry.retain(|(owner, registered)| {
+            let found = *owner == pid && *registered == path;
+            owned |= found;
+            !found
+        })
+    });
+    if owned {
+        remove_at(libc::AT_FDCWD, &path, 0);
+    }
+}
+
+fn fatal_action() -> SigAction {
+    SigAction::new(
+        SigHandler::Handler(fatal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    )
+}
+
+fn install_handlers() {
+    INSTALLED.store(true, Ordering::SeqCst);
+    let action = fatal_action();
+    for &sig in &FATAL_SIGNALS {
+        // keep ignored signals ignored, e.g. SIGINT in background jobs,
+        // and trapped signals trapped
+        if let Ok(old) = unsafe { signal::sigaction(sig, &action) } {
+            if old.handler() != SigHandler::SigDfl {
+                unsafe { signal::sigaction(sig, &old) }.ok();
+            }
+        }
+    }
+}
+
+/// Make the signal behave as without a trap: remove the registered paths
+/// and die if it is fatal and paths were ever registered, else the default.
+pub fn untrapped(sig: Signal) -> nix::Result<()> {
+    let action = if INSTALLED.load(Ordering::SeqCst) && FATAL_SIGNALS.contains(&sig) {
+        fatal_action()
+    } else {
+        SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty())
+    };
+    unsafe { signal::sigaction(sig, &action) }.map(drop)
+}
+
+extern "C" fn fatal(sig: libc::c_int) {
+    let pid = unsafe { libc::getpid() };
+    let registry = REGISTRY.load(Ordering::SeqCst);
+    if !registry.is_null() {
+        for (owner, path) in unsafe { (*registry).iter() } {
+            if *owner == pid {
+                remove_at(libc::AT_FDCWD, path, 0);
+            }
+        }
+    }
+    // die from the signal as if there was no handler
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+// remove a file or a directory tree, returns true on success
+fn remove_at(dirfd: RawFd, name: &CStr, depth: usize) -> bool {
+    if unsafe { libc::unlinkat(dirfd, name.as_ptr(), 0) } == 0 {
+        return true;
+    }
+    if depth > MAX_DEPTH {
+        return false;
+    }
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dirfd, name.as_ptr(), flags) };
+    if fd < 0 {
+        return false;
+    }
+    // removing entries while reading may skip others, so read until nothing is removed
+    let mut buf = [0u8; 4096];
+    let mut removed = true;
+    while removed {
+        removed = false;
+        unsafe { libc::lseek(fd, 0, libc::SEEK_SET) };
+        loop {
+            let count =
+                unsafe { libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len()) };
+            if count <= 0 {
+                break;
+            }
+            let mut offset = 0;
+            while offset < count as usize {
+                // struct linux_dirent64: d_ino, d_off, d_reclen, d_type, d_name
+                let reclen = u16::from_ne_bytes([buf[offset + 16], buf[offset + 17]]) as usize;
+                let entry = unsafe { CStr::from_ptr(buf[offset + 19..].as_ptr().cast()) };
+                if entry.to_bytes() != b"." && entry.to_bytes() != b".." {
+                    removed |= remove_at(fd, entry, depth + 1);
+                }
+                offset += reclen;
+            }
+        }
+    }
+    unsafe { libc::close(fd) };
+    unsafe { libc::unlinkat(dirfd, name.as_ptr(), libc::AT_REMOVEDIR) == 0 }
+}
@@ -1,12 +1,71 @@
+use nix::{sys::stat::Mode, unistd};
+use std::ffi::CString;
 use std::io;
+use std::os::unix::io::RawFd;
 
+#[derive(Debug)]
 pub struct Command<'a> {
     pub args: Vec<&'a str>,
     pub background: bool,
-    pub inredirect: Option<&'a str>,
-    pub outredirect: Option<&'a str>,
+    /// Redirections applied in order.
+    pub redirects: Vec<Redirect<'a>>,
 }
 
+/// How the commands of a group run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKind {
+    /// `( LIST )` in a child process.
+    Subshell,
+    /// `{ LIST; }` in the shell itself.
+    Brace,
+}
+
+/// Commands grouped by parentheses or braces.
+#[derive(Debug)]
+pub struct Group<'a> {
+    pub kind: GroupKind,
+    pub list: Vec<Node<'a>>,
+    pub redirects: Vec<Redirect<'a>>,
+    pub background: bool,
+}
+
+/// Command of a list.
+#[derive(Debug)]
+pub enum Node<'a> {
+    Command(Command<'a>),
+    Group(Group<'a>),
+}
+
+/// What a redirected descriptor refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// File opened for reading, `<FILE`.
+    Read(&'a str),
+    /// File truncated and opened for writing, `>FILE`.
+    Write(&'a str),
+    /// File truncated and opened for writing even with noclobber, `>|FILE`.
+    Clobber(&'a str),
+    /// File opened for appending, `>>FILE`.
+    Append(&'a str),
+    /// Copy of the descriptor, `>&N` or `<&N`.
+    Dup(RawFd),
+    /// Closed, `>&-` or `<&-`.
+    Close,
+}
+
+/// Redirection of the descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redirect<'a> {
+    pub fd: RawFd,
+    pub target: Target<'a>,
+}
+
+/// Base mode of new files, the umask decides the final permissions.
+pub const FILE_MODE: Mode = Mode::from_bits_truncate(0o666);
+
+/// Base mode of new directories, the umask decides the final permissions.
+pub const DIR_MODE: Mode = Mode::from_bits_truncate(0o777);
+
 // ********** helper functions **********
 
 pub fn report_error(err: &io::Error) {
@@ -16,3 +75,40 @@ pub fn report_error(err: &io::Error) {
 pub fn report_nixerror(err: &nix::errno::Errno) {
     eprintln!("Error: {}", err);
 }
+
+pub fn isatty(fd: RawFd) -> bool {
+    unistd::isatty(fd).unwrap_or(false)
+}
+
+/// Width of the terminal attached to stdout, defaults to 80 columns.
+pub fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if res == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+/// Format the time given in seconds since the epoch as a local time, see strftime(3).
+pub fn format_time(secs: i64, fmt: &str) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0u8; 128];
+    let fmt = match CString::new(fmt) {
+        Ok(fmt) => fmt,
+        Err(_) => return String::new(),
+    };
+    let len = unsafe {
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            fmt.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
@@ -0,0 +1,255 @@
+// Completion of the word before the cursor.
+
+use nix::unistd;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+
+use crate::common::Target;
+use crate::editor::line::str_width;
+use crate::parser::{self, Role};
+use crate::shell::{
+    eval::eval,
+    exec::{fork_child, wait_process},
+    resolve,
+};
+use crate::state::State;
+
+/// Completer of the arguments of a command, registered by the `complete` builtin.
+#[derive(Debug, Clone)]
+pub enum Completer {
+    /// Candidates from the list of words.
+    Words(Vec<String>),
+    /// Candidates printed by the command, one per line. The command gets
+    /// the line up to the cursor in COMP_LINE and the word in COMP_WORD.
+    Command(String),
+}
+
+#[derive(Debug)]
+pub struct Completion {
+    /// Start of the completed word in the text.
+    pub start: usize,
+    /// Open quote before the word.
+    pub quote: Option<char>,
+    pub candidates: Vec<String>,
+}
+
+// the file of a redirection word, e.g. `2>>log`
+fn redirected_file(word: &str) -> Option<&str> {
+    match parser::redirect(word)?.target {
+        Target::Read(file) | Target::Write(file) | Target::Clobber(file) | Target::Append(file) => {
+            Some(file)
+        }
+        Target::Dup(_) | Target::Close => None,
+    }
+}
+
+/// Complete the word before the cursor depending on its place and its prefix:
+/// commands, `$` variables, `%` jobs, `<`/`>` redirections or arguments.
+pub fn complete(state: &State, text: &str, cursor: usize) -> Completion {
+    let before = &text[..cursor];
+    let spans = parser::spans(before);
+    let roles = parser::roles(before, &spans);
+    let separated = |role: &Role| matches!(role, Role::Operator | Role::Background);
+    // the last token is the word unless a new one starts at the cursor
+    let (word, quote, at_command) = match (spans.last(), roles.last()) {
+        (Some(span), Some(role)) if span.end == cursor && !separated(role) => {
+            (&before[span.start..], span.quote, *role == Role::Command)
+        }
+        (_, role) => ("", None, role.is_none_or(separated)),
+    };
+    let candidates = if let Some(name) = word.strip_prefix('$') {
+        variables(state, name)
+    } else if let Some(spec) = word.strip_prefix('%') {
+        jobs(state, spec)
+    } else if let Some(file) = redirected_file(word) {
+        let op = &word[..word.len() - file.len()];
+        files(state, file, false)
+            .into_iter()
+            .map(|file| format!("{}{}", op, file))
+            .collect()
+    } else if at_command {
+        commands(state, word)
+    } else {
+        // the command of the word is the last one before it
+        let command = spans
+            .iter()
+            .zip(&roles)
+            .rev()
+            .find(|(_, &role)| role == Role::Command)
+            .map_or("", |(span, _)| &before[span.start..span.end]);
+        let completer = state.completers.borrow().get(command).cloned();
+        match completer {
+            Some(Completer::Words(words)) => matching(words, word),
+            Some(Completer::Command(command)) => {
+                matching(capture(state, &command, before, word), word)
+            }
+            None => files(state, word, false),
+        }
+    };
+    Completion {
+        start: cursor - word.len(),
+        quote,
+        candidates,
+    }
+}
+
+fn matching<I: IntoIterator<Item = String>>(words: I, prefix: &str) -> Vec<String> {
+    let words: BTreeSet<_> = words
+        .into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .collect();
+    words.into_iter().collect()
+}
+
+fn variables(state: &State, prefix: &str) -> Vec<String> {
+    let names = state.vars.borrow().keys().cloned().collect::<Vec<_>>();
+    matching(names, prefix)
+        .into_iter()
+        .map(|name| format!("${}", name))
+        .collect()
+}
+
+fn jobs(state: &State, prefix: &str) -> Vec<String> {
+    let ids = state
+        .jobs
+        .borrow()
+        .iter()
+        .map(|job| job.id.to_string())
+        .collect::<Vec<_>>();
+    matching(ids, prefix)
+        .into_iter()
+        .map(|id| format!("%{}", id))
+        .collect()
+}
+
+// builtins and executables on PATH, or paths to executables
+fn commands(state: &State, prefix: &str) -> Vec<String> {
+    if prefix.contains('/') {
+        return files(state, prefix, true);
+    }
+    let mut names: Vec<_> = state
+        .builtins
+        .borrow()
+        .iter()
+        .map(|builtin| builtin.command.to_string())
+        .collect();
+    let path = state.get_var("PATH").unwrap_or_default();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && resolve::is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+    matching(names, prefix)
+}
+
+// names in the directory of the word, directories end with a slash
+fn files(state: &State, word: &str, executables: bool) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let path = match (dir.strip_prefix("~/"), state.get_var("HOME")) {
+        (Some(rest), Some(home)) => format!("{}/{}", home, rest),
+        _ if dir.is_empty() => String::from("."),
+        _ => dir.to_string(),
+    };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // hidden files only when asked for
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            names.push(format!("{}{}/", dir, name));
+        } else if !executables || resolve::is_executable(&path) {
+            names.push(format!("{}{}", dir, name));
+        }
+    }
+    names.sort();
+    names
+}
+
+// run the completer command in a child and collect its output lines
+fn capture(state: &State, command: &str, line: &str, word: &str) -> Vec<String> {
+    let (fdin, fdout) = match unistd::pipe() {
+        Ok(fds) => fds,
+        Err(_) => return Vec::new(),
+    };
+    let child = fork_child(&mut || {
+        unistd::dup2(fdout, libc::STDOUT_FILENO).ok();
+        unistd::close(fdin).ok();
+        unistd::close(fdout).ok();
+        state.set_var("COMP_LINE", line);
+        state.set_var("COMP_WORD", word);
+        eval(state, command);
+        io::stdout().flush().ok();
+    });
+    unistd::close(fdout).ok();
+    let mut output = String::new();
+    unsafe { File::from_raw_fd(fdin) }
+        .read_to_string(&mut output)
+        .ok();
+    if let Ok(pid) = child {
+        wait_process(pid).ok();
+    }
+    output.lines().map(String::from).collect()
+}
+
+/// Longest common prefix of the candidates.
+pub fn common_prefix(candidates: &[String]) -> &str {
+    let first = match candidates.first() {
+        Some(first) => first.as_str(),
+        None => return "",
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first[..len]
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, ch1), ch2)| ch1 != ch2)
+            .map_or(len.min(candidate.len()), |((i, _), _)| i);
+    }
+    &first[..len]
+}
+
+/// Name of the candidate shown in the list, only the last part of a path.
+pub fn display_name(candidate: &str) -> &str {
+    let name = candidate.trim_end_matches('/');
+    let start = name.rfind('/').map_or(0, |i| i + 1);
+    &candidate[start..]
+}
+
+/// Candidates arranged in columns for the terminal width, rows end with CR LF.
+pub fn columns(names: &[&str], width: usize) -> String {
+    let column = names.iter().map(|name| str_width(name)).max().unwrap_or(0) + 2;
+    let cols = (width / column).max(1);
+    let rows = names.len().div_ceil(cols);
+    let mut out = String::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            if let Some(name) = names.get(col * rows + row) {
+                out.push_str(name);
+                if col + 1 < cols && (col + 1) * rows + row < names.len() {
+                    out.push_str(&" ".repeat(column - str_width(name)));
+                }
+            }
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
@@ -0,0 +1,121 @@
+// Colors of the edited command, the tokens are found by the parser of the shell.
+
+use std::path::Path;
+
+use crate::parser::{self, Role};
+use crate::shell::resolve;
+use crate::state::State;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    /// Builtin or external command which can be run.
+    Command,
+    /// Command which is not found.
+    Unknown,
+    String,
+    Redirect,
+    Background,
+    Variable,
+    /// Unterminated quote.
+    Error,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Plain => "\x1b[0m",
+            Style::Command => "\x1b[32m",
+            Style::Unknown => "\x1b[31m",
+            Style::String => "\x1b[33m",
+            Style::Redirect => "\x1b[36m",
+            Style::Background => "\x1b[35m",
+            Style::Variable => "\x1b[34m",
+            Style::Error => "\x1b[41m",
+        }
+    }
+}
+
+// is there a builtin or an executable of the name
+fn resolves(state: &State, name: &str) -> bool {
+    if state.builtins.borrow().find(name).is_some() {
+        return true;
+    }
+    if name.contains('/') {
+        return resolve::is_executable(Path::new(name));
+    }
+    !resolve::search_path(state, name).is_empty()
+}
+
+// mark the `$NAME` references in the range
+fn variables(text: &str, start: usize, end: usize, styles: &mut [Style]) {
+    let mut chars = text[start..end].char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        if ch != '$' {
+            continue;
+        }
+        let mut len = 1;
+        while let Some(&(_, ch)) = chars.peek() {
+            if !(ch.is_alphanumeric() || ch == '_') {
+                break;
+            }
+            len += ch.len_utf8();
+            chars.next();
+        }
+        if len > 1 {
+            styles[start + i..start + i + len].fill(Style::Variable);
+        }
+    }
+}
+
+/// Style of every byte of the text.
+pub fn styles(state: &State, text: &str) -> Vec<Style> {
+    let mut styles = vec![Style::Plain; text.len()];
+    let spans = parser::spans(text);
+    for (span, role) in spans.iter().zip(parser::roles(text, &spans)) {
+        // quoted tokens with their quotes
+        let (start, end) = match span.quote {
+            Some(_) => (span.start - 1, (span.end + 1).min(text.len())),
+            None => (span.start, span.end),
+        };
+        let style = match role {
+            _ if !span.closed => Style::Error,
+            Role::Command if resolves(state, &text[span.start..span.end]) => Style::Command,
+            Role::Command => Style::Unknown,
+            Role::InRedirect | Role::OutRedirect => Style::Redirect,
+            Role::Background => Style::Background,
+            Role::Operator => Style::Plain,
+            Role::Argument if span.quote.is_some() => Style::String,
+            Role::Argument => Style::Plain,
+        };
+        styles[start..end].fill(style);
+        if role == Role::Argument && span.quote != Some('\'') {
+            variables(text, span.start, span.end, &mut styles);
+        }
+    }
+    styles
+}
+
+/// The text with the escape sequences of the styles, the colors end with the text.
+pub fn paint(text: &str, styles: &[Style]) -> String {
+    let mut out = String::new();
+    let mut current = Style::Plain;
+    for (i, ch) in text.char_indices() {
+        let style = styles.get(i).copied().unwrap_or(Style::Plain);
+        if style != current {
+            // reset the background too
+            if current != Style::Plain {
+                out.push_str(Style::Plain.code());
+            }
+            if style != Style::Plain {
+                out.push_str(style.code());
+            }
+            current = style;
+        }
+        out.push(ch);
+    }
+    if current != Style::Plain {
+        out.push_str(Style::Plain.code());
+    }
+    out
+}
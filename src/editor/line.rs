@@ -0,0 +1,200 @@
+// Edited text with a cursor, positions are byte offsets at char boundaries.
+
+/// Number of terminal columns taken by the character.
+pub fn char_width(ch: char) -> usize {
+    let code = ch as u32;
+    match code {
+        0 => 0,
+        // combining marks, zero width spaces and variation selectors
+        0x0300..=0x036f
+        | 0x0483..=0x0489
+        | 0x0591..=0x05bd
+        | 0x0610..=0x061a
+        | 0x064b..=0x065f
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200b..=0x200f
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f => 0,
+        // east asian wide and fullwidth characters, emoji
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// Number of terminal columns taken by the text, ANSI escape sequences take none.
+pub fn str_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            // ESC [ parameters final byte
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|ch| ('@'..='~').contains(ch));
+            }
+        } else {
+            width += char_width(ch);
+        }
+    }
+    width
+}
+
+fn is_word(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+#[derive(Debug, Default)]
+pub struct Line {
+    pub text: String,
+    pub cursor: usize,
+}
+
+impl Line {
+    pub fn new() -> Line {
+        Line::default()
+    }
+
+    /// Position of the previous character, combining marks stay with their base.
+    pub fn prev_char(&self, pos: usize) -> usize {
+        let mut chars = self.text[..pos].char_indices().rev();
+        for (i, ch) in chars.by_ref() {
+            if char_width(ch) > 0 || i == 0 {
+                return i;
+            }
+        }
+        0
+    }
+
+    /// Position of the next character, combining marks stay with their base.
+    pub fn next_char(&self, pos: usize) -> usize {
+        let mut chars = self.text[pos..].char_indices().skip(1);
+        chars
+            .find(|&(_, ch)| char_width(ch) > 0)
+            .map_or(self.text.len(), |(i, _)| pos + i)
+    }
+
+    /// Start of the current line of a multi-line text.
+    pub fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// End of the current line of a multi-line text.
+    pub fn line_end(&self) -> usize {
+        self.text[self.cursor..]
+            .find('\n')
+            .map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    /// Start of the word before the cursor.
+    pub fn word_start(&self) -> usize {
+        self.find_start(is_word)
+    }
+
+    /// End of the word after the cursor.
+    pub fn word_end(&self) -> usize {
+        let mut in_word = false;
+        for (i, ch) in self.text[self.cursor..].char_indices() {
+            if is_word(ch) {
+                in_word = true;
+            } else if in_word {
+                return self.cursor + i;
+            }
+        }
+        self.text.len()
+    }
+
+    /// Start of the whitespace delimited word before the cursor.
+    pub fn blank_word_start(&self) -> usize {
+        self.find_start(|ch| !ch.is_whitespace())
+    }
+
+    fn find_start<F: Fn(char) -> bool>(&self, in_word: F) -> usize {
+        let mut found = false;
+        for (i, ch) in self.text[..self.cursor].char_indices().rev() {
+            if in_word(ch) {
+                found = true;
+            } else if found {
+                return i + ch.len_utf8();
+            }
+        }
+        0
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Remove the text between the positions, the cursor moves to the start.
+    pub fn remove(&mut self, start: usize, end: usize) -> String {
+        let removed = self.text[start..end].to_string();
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        removed
+    }
+
+    /// Swap the characters around the cursor, or the last two at the end of the line.
+    pub fn transpose(&mut self) {
+        let end = if self.cursor == self.line_end() {
+            self.cursor
+        } else {
+            self.next_char(self.cursor)
+        };
+        let middle = self.prev_char(end);
+        let start = self.prev_char(middle);
+        if start < self.line_start() || start == middle {
+            return;
+        }
+        let swapped = format!("{}{}", &self.text[middle..end], &self.text[start..middle]);
+        self.text.replace_range(start..end, &swapped);
+        self.cursor = end;
+    }
+
+    // move to the column of the given line, or its end if it is shorter
+    fn move_to_column(&mut self, start: usize, column: usize) {
+        let end = self.text[start..]
+            .find('\n')
+            .map_or(self.text.len(), |i| start + i);
+        self.cursor = start;
+        while self.cursor < end && str_width(&self.text[start..self.cursor]) < column {
+            self.cursor = self.next_char(self.cursor);
+        }
+    }
+
+    /// Move to the previous line of a multi-line text, false on the first line.
+    pub fn up(&mut self) -> bool {
+        let start = self.line_start();
+        if start == 0 {
+            return false;
+        }
+        let column = str_width(&self.text[start..self.cursor]);
+        let prev = self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        self.move_to_column(prev, column);
+        true
+    }
+
+    /// Move to the next line of a multi-line text, false on the last line.
+    pub fn down(&mut self) -> bool {
+        let end = self.line_end();
+        if end == self.text.len() {
+            return false;
+        }
+        let column = str_width(&self.text[self.line_start()..self.cursor]);
+        self.move_to_column(end + 1, column);
+        true
+    }
+}
@@ -0,0 +1,498 @@
+// Interactive line editor with emacs key bindings, used when the shell is interactive.
+
+use std::io::{self, Write};
+
+use crate::common::terminal_width;
+use crate::parser;
+use crate::state::State;
+
+mod complete;
+mod highlight;
+mod line;
+mod term;
+
+pub use complete::Completer;
+
+use line::{char_width, str_width, Line};
+use term::{read_key, Key, RawMode};
+
+const KILL_RING_SIZE: usize = 16;
+
+// what the previous key did, consecutive kills are joined and yanks can be rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Last {
+    Kill,
+    Yank { start: usize, index: usize },
+    Other,
+}
+
+enum Outcome {
+    Continue,
+    Clear,
+    Search,
+    Complete,
+    Accept,
+    Cancel,
+    Eof,
+}
+
+// the line being edited and its place in the history
+struct Session<'h> {
+    line: Line,
+    last: Last,
+    history: &'h [String],
+    // index of the shown history entry, the length for the new line
+    index: usize,
+    // the new line while browsing the history
+    saved: String,
+}
+
+impl<'h> Session<'h> {
+    fn new(history: &'h [String]) -> Session<'h> {
+        Session {
+            line: Line::new(),
+            last: Last::Other,
+            history,
+            index: history.len(),
+            saved: String::new(),
+        }
+    }
+
+    // move up a line of the text, or to the previous history entry from its first line
+    fn up(&mut self) {
+        if !self.line.up() && self.index > 0 {
+            self.browse(self.index - 1);
+        }
+    }
+
+    // move down a line of the text, or to the next history entry from its last line
+    fn down(&mut self) {
+        if !self.line.down() && self.index < self.history.len() {
+            self.browse(self.index + 1);
+        }
+    }
+
+    fn browse(&mut self, index: usize) {
+        if self.index == self.history.len() {
+            self.saved = self.line.text.clone();
+        }
+        self.index = index;
+        self.line.text = match self.history.get(index) {
+            Some(entry) => entry.clone(),
+            None => self.saved.clone(),
+        };
+        self.line.cursor = self.line.text.len();
+    }
+}
+
+// incremental search backwards in the history, returns the key which ended it
+fn search(state: &State, session: &mut Session, view: &mut View) -> io::Result<Option<Key>> {
+    let original = (session.line.text.clone(), session.line.cursor);
+    let mut query = String::new();
+    let mut found = None;
+    loop {
+        let failed = if found.is_none() && !query.is_empty() {
+            "failed "
+        } else {
+            ""
+        };
+        view.prompt = format!("({}reverse-i-search)`{}': ", failed, query);
+        view.refresh(state, &session.line)?;
+        // search older entries than `end`
+        let end = match read_key(libc::STDIN_FILENO)? {
+            Some(Key::Char(ch)) => {
+                query.push(ch);
+                found.map_or(session.history.len(), |i| i + 1)
+            }
+            Some(Key::Backspace) => {
+                query.pop();
+                session.history.len()
+            }
+            Some(Key::Ctrl('r')) => found.unwrap_or(session.history.len()),
+            Some(Key::Ctrl('g')) | Some(Key::Escape) | None => {
+                session.line.text = original.0;
+                session.line.cursor = original.1;
+                return Ok(None);
+            }
+            Some(key) => return Ok(Some(key)),
+        };
+        if query.is_empty() {
+            continue;
+        }
+        let next = session.history[..end]
+            .iter()
+            .rposition(|entry| entry.contains(&query));
+        if let Some(i) = next {
+            let entry = &session.history[i];
+            session.line.text = entry.clone();
+            session.line.cursor = entry.find(&query).unwrap_or(0);
+            session.index = i;
+            found = next;
+        } else if end == session.history.len() {
+            found = None;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    killring: Vec<String>,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::default()
+    }
+
+    /// Read a line with the prompt, incomplete commands continue on the next line
+    /// with the continuation prompt. Returns None at the end of input.
+    pub fn read_line(
+        &mut self,
+        state: &State,
+        prompt: &str,
+        cont: &str,
+    ) -> io::Result<Option<String>> {
+        let _raw = match RawMode::enable(libc::STDIN_FILENO) {
+            Ok(raw) => raw,
+            Err(_) => return read_plain(prompt, cont),
+        };
+        // only the last line of the prompt is redrawn
+        let prompt = match prompt.rsplit_once('\n') {
+            Some((head, last)) => {
+                print!("{}\r\n", head.replace('\n', "\r\n"));
+                last
+            }
+            None => prompt,
+        };
+        // a copy, completion commands may run the history builtin
+        let history = state.history.borrow().entries().to_vec();
+        let mut view = View::new(prompt, cont);
+        let mut session = Session::new(&history);
+        view.refresh(state, &session.line)?;
+        let mut pending = None;
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => match read_key(libc::STDIN_FILENO)? {
+                    Some(key) => key,
+                    None => return Ok(None),
+                },
+            };
+            match self.dispatch(key, &mut session) {
+                Outcome::Continue => view.refresh(state, &session.line)?,
+                Outcome::Clear => {
+                    view.clear();
+                    view.refresh(state, &session.line)?;
+                }
+                Outcome::Search => {
+                    pending = search(state, &mut session, &mut view)?;
+                    view.prompt = prompt.to_string();
+                    view.refresh(state, &session.line)?;
+                }
+                Outcome::Complete => {
+                    complete(state, &mut session.line, &mut view)?;
+                    view.refresh(state, &session.line)?;
+                }
+                Outcome::Accept => {
+                    view.finish(state, &mut session.line, "")?;
+                    return Ok(Some(session.line.text));
+                }
+                Outcome::Cancel => {
+                    view.finish(state, &mut session.line, "^C")?;
+                    return Ok(Some(String::new()));
+                }
+                Outcome::Eof => {
+                    view.finish(state, &mut session.line, "")?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, key: Key, session: &mut Session) -> Outcome {
+        let line = &mut session.line;
+        let prev = session.last;
+        let last = &mut session.last;
+        *last = Last::Other;
+        match key {
+            Key::Enter if parser::is_continued(&line.text) => {
+                line.cursor = line.text.len();
+                line.insert("\n");
+            }
+            Key::Enter => return Outcome::Accept,
+            Key::Ctrl('c') => return Outcome::Cancel,
+            Key::Ctrl('d') if line.text.is_empty() => return Outcome::Eof,
+            Key::Char(ch) => line.insert(ch.encode_utf8(&mut [0; 4])),
+            // motion
+            Key::Ctrl('a') | Key::Home => line.cursor = line.line_start(),
+            Key::Ctrl('e') | Key::End => line.cursor = line.line_end(),
+            Key::Ctrl('b') | Key::Left => line.cursor = line.prev_char(line.cursor),
+            Key::Ctrl('f') | Key::Right => line.cursor = line.next_char(line.cursor),
+            Key::Alt('b') | Key::WordLeft => line.cursor = line.word_start(),
+            Key::Alt('f') | Key::WordRight => line.cursor = line.word_end(),
+            Key::Ctrl('p') | Key::Up => session.up(),
+            Key::Ctrl('n') | Key::Down => session.down(),
+            Key::Ctrl('r') => return Outcome::Search,
+            Key::Tab => return Outcome::Complete,
+            // deletion
+            Key::Backspace if line.cursor > 0 => {
+                line.remove(line.prev_char(line.cursor), line.cursor);
+            }
+            Key::Ctrl('d') | Key::Delete if line.cursor < line.text.len() => {
+                let cursor = line.cursor;
+                line.remove(cursor, line.next_char(cursor));
+            }
+            Key::Ctrl('t') => line.transpose(),
+            // kill ring
+            Key::Ctrl('k') => {
+                // at the end of a line kill the newline
+                let end = match line.line_end() {
+                    end if end == line.cursor && end < line.text.len() => end + 1,
+                    end => end,
+                };
+                self.kill(line, line.cursor, end, false, prev);
+                *last = Last::Kill;
+            }
+            Key::Ctrl('u') => {
+                self.kill(line, line.line_start(), line.cursor, true, prev);
+                *last = Last::Kill;
+            }
+            Key::Ctrl('w') => {
+                self.kill(line, line.blank_word_start(), line.cursor, true, prev);
+                *last = Last::Kill;
+            }
+            Key::AltBackspace => {
+                self.kill(line, line.word_start(), line.cursor, true, prev);
+                *last = Last::Kill;
+            }
+            Key::Alt('d') => {
+                self.kill(line, line.cursor, line.word_end(), false, prev);
+                *last = Last::Kill;
+            }
+            Key::Ctrl('y') => {
+                if let Some(text) = self.killring.last() {
+                    let start = line.cursor;
+                    line.insert(text);
+                    let index = self.killring.len() - 1;
+                    *last = Last::Yank { start, index };
+                }
+            }
+            Key::Alt('y') => {
+                // replace the yanked text with the previous kill
+                if let Last::Yank { start, index } = prev {
+                    let index = (index + self.killring.len() - 1) % self.killring.len();
+                    line.remove(start, line.cursor);
+                    line.insert(&self.killring[index]);
+                    *last = Last::Yank { start, index };
+                }
+            }
+            Key::Ctrl('l') => return Outcome::Clear,
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    fn kill(&mut self, line: &mut Line, start: usize, end: usize, backward: bool, prev: Last) {
+        if start >= end {
+            return;
+        }
+        let text = line.remove(start, end);
+        match self.killring.last_mut() {
+            Some(killed) if prev == Last::Kill => {
+                if backward {
+                    killed.insert_str(0, &text);
+                } else {
+                    killed.push_str(&text);
+                }
+            }
+            _ => {
+                if self.killring.len() == KILL_RING_SIZE {
+                    self.killring.remove(0);
+                }
+                self.killring.push(text);
+            }
+        }
+    }
+}
+
+// complete the word before the cursor, or list the candidates if they differ
+fn complete(state: &State, line: &mut Line, view: &mut View) -> io::Result<()> {
+    let completion = complete::complete(state, &line.text, line.cursor);
+    let candidates = &completion.candidates;
+    let word = &line.text[completion.start..line.cursor];
+    match candidates.len() {
+        0 => {
+            print!("\x07");
+            io::stdout().flush()
+        }
+        1 => {
+            let candidate = &candidates[0];
+            let mut text = candidate.clone();
+            // quote the words with spaces, close the quote after a whole word
+            if completion.quote.is_none() && candidate.contains(char::is_whitespace) {
+                text.insert(0, '"');
+            }
+            if !candidate.ends_with('/') {
+                match completion.quote {
+                    Some(quote) => text.push(quote),
+                    None if text.starts_with('"') => text.push('"'),
+                    None => {}
+                }
+                text.push(' ');
+            }
+            line.remove(completion.start, line.cursor);
+            line.insert(&text);
+            Ok(())
+        }
+        _ => {
+            let prefix = complete::common_prefix(candidates);
+            if prefix.len() > word.len() {
+                let prefix = prefix.to_string();
+                line.remove(completion.start, line.cursor);
+                line.insert(&prefix);
+                return Ok(());
+            }
+            // list them below the line and show the line again
+            let names: Vec<_> = candidates
+                .iter()
+                .map(|candidate| complete::display_name(candidate))
+                .collect();
+            let cursor = line.cursor;
+            line.cursor = line.text.len();
+            view.refresh(state, line)?;
+            line.cursor = cursor;
+            print!("\r\n{}", complete::columns(&names, terminal_width()));
+            view.row = 0;
+            Ok(())
+        }
+    }
+}
+
+// without a terminal print the prompts and read lines as usual
+fn read_plain(prompt: &str, cont: &str) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    print!("{}", prompt);
+    loop {
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(if line.is_empty() { None } else { Some(line) });
+        }
+        if !parser::is_continued(&line) {
+            return Ok(Some(line));
+        }
+        print!("{}", cont);
+    }
+}
+
+// ********** screen **********
+
+// the edited line on the screen, it may span several rows
+struct View {
+    prompt: String,
+    cont: String,
+    // row of the cursor relative to the first row
+    row: usize,
+}
+
+// screen position after writing the text at the position, wrapping at the width
+fn advance((mut row, mut col): (usize, usize), text: &str, width: usize) -> (usize, usize) {
+    for ch in text.chars() {
+        let w = char_width(ch);
+        if col + w > width {
+            row += 1;
+            col = 0;
+        }
+        col += w;
+        if col >= width {
+            row += 1;
+            col = 0;
+        }
+    }
+    (row, col)
+}
+
+impl View {
+    fn new(prompt: &str, cont: &str) -> View {
+        View {
+            prompt: prompt.to_string(),
+            cont: cont.to_string(),
+            row: 0,
+        }
+    }
+
+    // screen positions of the cursor and of the end of the text
+    fn layout(&self, line: &Line, width: usize) -> ((usize, usize), (usize, usize)) {
+        let mut pos = (0, 0);
+        let mut cursor = None;
+        let mut offset = 0;
+        for (i, text) in line.text.split('\n').enumerate() {
+            if i > 0 {
+                pos = (pos.0 + 1, 0);
+            }
+            let prompt = if i == 0 { &self.prompt } else { &self.cont };
+            // the prompt is measured without its escape sequences
+            pos = advance(pos, &" ".repeat(str_width(prompt)), width);
+            if cursor.is_none() && line.cursor <= offset + text.len() {
+                cursor = Some(advance(pos, &text[..line.cursor - offset], width));
+            }
+            pos = advance(pos, text, width);
+            offset += text.len() + 1;
+        }
+        (cursor.unwrap_or(pos), pos)
+    }
+
+    fn refresh(&mut self, state: &State, line: &Line) -> io::Result<()> {
+        let width = terminal_width();
+        let (cursor, end) = self.layout(line, width);
+        let mut out = String::new();
+        // back to the first row and clear everything below
+        if self.row > 0 {
+            out.push_str(&format!("\x1b[{}A", self.row));
+        }
+        out.push_str("\r\x1b[J");
+        let styles = highlight::styles(state, &line.text);
+        let mut offset = 0;
+        for (i, text) in line.text.split('\n').enumerate() {
+            if i > 0 {
+                out.push_str("\r\n");
+            }
+            out.push_str(if i == 0 { &self.prompt } else { &self.cont });
+            out.push_str(&highlight::paint(text, &styles[offset..]));
+            offset += text.len() + 1;
+        }
+        // the terminal keeps the cursor at the last column after filling a row
+        let last = line.text.rsplit('\n').next().unwrap_or("");
+        let prompt = if line.text.contains('\n') {
+            &self.cont
+        } else {
+            &self.prompt
+        };
+        if end.1 == 0 && str_width(prompt) + str_width(last) > 0 {
+            out.push_str("\r\n");
+        }
+        if end.0 > cursor.0 {
+            out.push_str(&format!("\x1b[{}A", end.0 - cursor.0));
+        }
+        out.push('\r');
+        if cursor.1 > 0 {
+            out.push_str(&format!("\x1b[{}C", cursor.1));
+        }
+        self.row = cursor.0;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn clear(&mut self) {
+        print!("\x1b[H\x1b[2J");
+        self.row = 0;
+    }
+
+    // show the whole text with a mark and move below it
+    fn finish(&mut self, state: &State, line: &mut Line, mark: &str) -> io::Result<()> {
+        line.cursor = line.text.len();
+        self.refresh(state, line)?;
+        print!("{}\r\n", mark);
+        io::stdout().flush()
+    }
+}
@@ -0,0 +1,172 @@
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+    sys::termios::{
+        self, ControlFlags, InputFlags, LocalFlags, OutputFlags, SetArg, SpecialCharacterIndices,
+        Termios,
+    },
+    unistd,
+};
+use std::os::unix::io::RawFd;
+
+/// Raw mode of the terminal, the original mode is restored on drop.
+pub struct RawMode {
+    fd: RawFd,
+    saved: Termios,
+}
+
+impl RawMode {
+    pub fn enable(fd: RawFd) -> nix::Result<RawMode> {
+        let saved = termios::tcgetattr(fd)?;
+        let mut raw = saved.clone();
+        raw.input_flags.remove(
+            InputFlags::BRKINT
+                | InputFlags::ICRNL
+                | InputFlags::INPCK
+                | InputFlags::ISTRIP
+                | InputFlags::IXON,
+        );
+        raw.output_flags.remove(OutputFlags::OPOST);
+        raw.control_flags.insert(ControlFlags::CS8);
+        raw.local_flags
+            .remove(LocalFlags::ECHO | LocalFlags::ICANON | LocalFlags::IEXTEN | LocalFlags::ISIG);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(fd, SetArg::TCSADRAIN, &raw)?;
+        Ok(RawMode { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        termios::tcsetattr(self.fd, SetArg::TCSADRAIN, &self.saved).ok();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Control character, e.g. `Ctrl('a')` for Ctrl-A.
+    Ctrl(char),
+    /// Meta/Alt character, sent as ESC and the character.
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Escape,
+    Unknown,
+}
+
+// how long to wait for the rest of an escape sequence
+const ESCAPE_TIMEOUT: i32 = 50;
+
+fn read_byte(fd: RawFd) -> nix::Result<Option<u8>> {
+    let mut buf = [0; 1];
+    loop {
+        match unistd::read(fd, &mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// read the next byte if it comes soon enough
+fn read_byte_timeout(fd: RawFd) -> nix::Result<Option<u8>> {
+    let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+    match poll(&mut fds, ESCAPE_TIMEOUT) {
+        Ok(0) => Ok(None),
+        Ok(_) => read_byte(fd),
+        Err(Errno::EINTR) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Read a key from the terminal, None at the end of input.
+pub fn read_key(fd: RawFd) -> nix::Result<Option<Key>> {
+    let byte = match read_byte(fd)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x08 | 0x7f => Key::Backspace,
+        0x1b => read_escape(fd)?,
+        0..=0x1f => Key::Ctrl((byte + b'a' - 1) as char),
+        0x20..=0x7e => Key::Char(byte as char),
+        _ => read_utf8(fd, byte)?,
+    };
+    Ok(Some(key))
+}
+
+fn read_utf8(fd: RawFd, first: u8) -> nix::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Unknown),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(fd)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(Key::Unknown),
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .map_or(Key::Unknown, Key::Char))
+}
+
+fn read_escape(fd: RawFd) -> nix::Result<Key> {
+    let byte = match read_byte_timeout(fd)? {
+        Some(byte) => byte,
+        None => return Ok(Key::Escape),
+    };
+    match byte {
+        b'[' | b'O' => read_sequence(fd),
+        0x7f | 0x08 => Ok(Key::AltBackspace),
+        0x20..=0x7e => Ok(Key::Alt(byte as char)),
+        _ => Ok(Key::Unknown),
+    }
+}
+
+// CSI or SS3 sequence, e.g. ESC [ A or ESC [ 1 ; 5 C
+fn read_sequence(fd: RawFd) -> nix::Result<Key> {
+    let mut params = String::new();
+    let last = loop {
+        match read_byte_timeout(fd)? {
+            Some(byte @ 0x40..=0x7e) => break byte,
+            Some(byte) => params.push(byte as char),
+            None => return Ok(Key::Unknown),
+        }
+    };
+    // modifiers 3 (Alt) and 5 (Ctrl) move by words
+    let word = params.ends_with(";3") || params.ends_with(";5");
+    let key = match (last, params.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) if word => Key::WordRight,
+        (b'D', _) if word => Key::WordLeft,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1") | (b'~', "7") => Key::Home,
+        (b'F', _) | (b'~', "4") | (b'~', "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
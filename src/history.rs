@@ -0,0 +1,272 @@
+// Command history of the interactive shell with csh style `!` expansion.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Default number of remembered commands, see HISTSIZE.
+pub const DEFAULT_SIZE: usize = 1000;
+
+/// Which commands are not added, see HISTCONTROL.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Control {
+    /// Skip a command equal to the previous one.
+    pub ignoredups: bool,
+    /// Remove the earlier copies of a command.
+    pub erasedups: bool,
+}
+
+impl Control {
+    /// Parse a colon separated list such as `ignoredups:erasedups`.
+    pub fn parse(spec: &str) -> Control {
+        let mut control = Control::default();
+        for word in spec.split(':') {
+            match word {
+                "ignoredups" | "ignoreboth" => control.ignoredups = true,
+                "erasedups" => control.erasedups = true,
+                _ => {}
+            }
+        }
+        control
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Vec<String>,
+    // number of the first entry, the numbers stay when old entries are dropped
+    base: usize,
+    size: usize,
+    file: Option<PathBuf>,
+}
+
+// one entry per line in the file
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(ch),
+        }
+    }
+    entry
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: Vec::new(),
+            base: 1,
+            size: DEFAULT_SIZE,
+            file: None,
+        }
+    }
+
+    /// Load the history from the file, new commands are appended to it.
+    pub fn load(&mut self, file: &Path, size: usize) -> io::Result<()> {
+        self.size = size;
+        self.file = Some(file.to_path_buf());
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let lines: Vec<_> = text.lines().collect();
+        let skip = lines.len().saturating_sub(size);
+        self.entries = lines[skip..].iter().map(|line| unescape(line)).collect();
+        // keep the file from growing without bounds
+        if skip > 0 {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Write all entries to the history file.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            let text: String = self
+                .entries
+                .iter()
+                .map(|entry| escape(entry) + "\n")
+                .collect();
+            fs::write(file, text)?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, entry: &str) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            let mut file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .mode(0o600)
+                .open(file)?;
+            writeln!(file, "{}", escape(entry))?;
+        }
+        Ok(())
+    }
+
+    /// Add a command, lines starting with a space are left out.
+    pub fn add(&mut self, line: &str, size: usize, control: Control) -> io::Result<()> {
+        let entry = line.trim_end_matches('\n');
+        if entry.trim().is_empty() || entry.starts_with(' ') {
+            return Ok(());
+        }
+        if control.ignoredups && self.entries.last().is_some_and(|last| last == entry) {
+            return Ok(());
+        }
+        self.size = size;
+        let mut rewrite = false;
+        if control.erasedups {
+            let len = self.entries.len();
+            self.entries.retain(|old| old != entry);
+            rewrite = self.entries.len() != len;
+        }
+        self.entries.push(entry.to_string());
+        if self.entries.len() > self.size {
+            let drop = self.entries.len() - self.size;
+            self.entries.drain(..drop);
+            self.base += drop;
+        }
+        if rewrite {
+            self.save()
+        } else {
+            self.append(entry)
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Entries with their numbers.
+    pub fn numbered(&self) -> impl Iterator<Item = (usize, &str)> {
+        let base = self.base;
+        self.entries
+            .iter()
+            .enumerate()
+            .map(move |(i, entry)| (base + i, entry.as_str()))
+    }
+
+    pub fn get(&self, number: usize) -> Option<&str> {
+        number
+            .checked_sub(self.base)
+            .and_then(|i| self.entries.get(i))
+            .map(String::as_str)
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.base = 1;
+        self.entries.clear();
+        self.save()
+    }
+
+    /// Delete the entry with the number, false if there is none.
+    pub fn delete(&mut self, number: usize) -> io::Result<bool> {
+        match number.checked_sub(self.base) {
+            Some(i) if i < self.entries.len() => {
+                self.entries.remove(i);
+                self.save()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // the entry for the event designator after `!`, with its length
+    fn event(&self, rest: &str) -> Option<(Option<&str>, usize)> {
+        if rest.starts_with('!') {
+            return Some((self.entries.last().map(String::as_str), 1));
+        }
+        let sign = usize::from(rest.starts_with('-'));
+        let digits = rest[sign..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .count();
+        if digits > 0 {
+            let number: usize = rest[sign..sign + digits].parse().ok()?;
+            let entry = if sign == 1 {
+                self.entries
+                    .len()
+                    .checked_sub(number)
+                    .and_then(|i| self.entries.get(i))
+                    .map(String::as_str)
+            } else {
+                self.get(number)
+            };
+            return Some((entry, sign + digits));
+        }
+        let len = rest
+            .find(|ch: char| ch.is_whitespace() || "=(\"'".contains(ch))
+            .unwrap_or(rest.len());
+        if len == 0 || sign == 1 {
+            return None;
+        }
+        let prefix = &rest[..len];
+        let entry = self
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.starts_with(prefix));
+        Some((entry.map(String::as_str), len))
+    }
+
+    /// Expand `!!`, `!n`, `!-n`, `!prefix` and a leading `^old^new^`,
+    /// None if there is nothing to expand.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        if let Some(rest) = line.strip_prefix('^') {
+            let rest = rest.trim_end_matches('\n');
+            let mut parts = rest.splitn(3, '^');
+            let old = parts.next().unwrap_or("");
+            let new = parts.next().unwrap_or("");
+            let tail = parts.next().unwrap_or("");
+            return match self.entries.last() {
+                Some(last) if !old.is_empty() && last.contains(old) => {
+                    Ok(Some(last.replacen(old, new, 1) + tail))
+                }
+                _ => Err(format!("^{}: substitution failed", rest)),
+            };
+        }
+        let mut expanded = String::new();
+        let mut changed = false;
+        let mut quoted = false;
+        let mut chars = line.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            if ch == '\'' {
+                quoted = !quoted;
+            }
+            let event = match ch {
+                '!' if !quoted => self.event(&line[i + 1..]),
+                _ => None,
+            };
+            match event {
+                Some((Some(entry), len)) => {
+                    expanded.push_str(entry);
+                    changed = true;
+                    // skip the designator
+                    line[i + 1..i + 1 + len].chars().for_each(|_| {
+                        chars.next();
+                    });
+                }
+                Some((None, len)) => {
+                    return Err(format!("{}: event not found", &line[i..i + 1 + len]));
+                }
+                None => expanded.push(ch),
+            }
+        }
+        Ok(if changed { Some(expanded) } else { None })
+    }
+}
@@ -0,0 +1,78 @@
+//! The frish shell as a library: parse command lines, create a shell state
+//! with the builtins, register more builtins and evaluate commands.
+//!
+//! The builtins of a `State` are added and removed by `State::register_builtin`
+//! and `State::unregister_builtin`, the table of the builtins is internal. The
+//! state is read and changed by its methods such as `get_var`, `set_var`,
+//! `option`, `set_option` and `status`.
+//!
+//! ```no_run
+//! let state = frish::State::new("embedded", false);
+//! state.register_builtin(frish::Builtin::new("hello", |_, _| {
+//!     println!("hello");
+//!     Ok(frish::Status::success())
+//! }, "Print hello"));
+//! let out = frish::eval_captured(&state, "hello").unwrap();
+//! assert_eq!(out.stdout, b"hello\n");
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[macro_use]
+extern crate log;
+
+mod builtins;
+mod cleanup;
+mod common;
+mod digest;
+mod editor;
+mod history;
+mod parser;
+mod shell;
+mod state;
+
+pub use builtins::{Builtin, BuiltinHandler};
+pub use common::{Command, Group, GroupKind, Node, Redirect, Target};
+pub use parser::{parse, parse_list, ParseError};
+pub use shell::eval::read_eval_loop;
+pub use shell::options;
+pub use state::{State, Status};
+
+/// Status and output of the commands evaluated by `eval_captured`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured {
+    pub status: Status,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Evaluate the commands of the text line by line, until its end or `exit`,
+/// and return the status of the last one.
+pub fn eval(state: &State, text: &str) -> Status {
+    shell::eval::eval_text(state, text);
+    state.status()
+}
+
+/// Evaluate the commands of the script file.
+pub fn eval_file(state: &State, path: &Path) -> io::Result<Status> {
+    let text = fs::read_to_string(path)?;
+    Ok(eval(state, &text))
+}
+
+/// Run the EXIT trap set by `trap` once, before the process exits.
+pub fn run_exit_trap(state: &State) {
+    shell::trap::run_exit(state);
+}
+
+/// Evaluate the commands with their standard output and error collected in memory,
+/// the descriptors 1 and 2 of the process are replaced for the time.
+pub fn eval_captured(state: &State, text: &str) -> io::Result<Captured> {
+    let (status, stdout, stderr) = shell::capture::capture(|| eval(state, text))?;
+    Ok(Captured {
+        status,
+        stdout,
+        stderr,
+    })
+}
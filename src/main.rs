@@ -1,25 +1,68 @@
+use log::info;
+use std::env;
 use std::process::exit;
 
-mod builtins;
-mod common;
-mod parser;
-mod shell;
-mod state;
+use frish::options::{self, ShellOption};
+use frish::{read_eval_loop, State};
 
-use crate::shell::eval::read_eval_loop;
-use crate::state::State;
+fn usage_error(msg: &str) -> ! {
+    eprintln!("frish: {}", msg);
+    eprintln!("Usage: frish [-euxnC] [-o OPTION]... [-c COMMAND]");
+    exit(2);
+}
 
-#[macro_use]
-extern crate log;
+// the options as for `set` and the command given by `-c COMMAND`
+fn parse_args() -> (Vec<(ShellOption, bool)>, Option<String>) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut settings = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            "-c" => match args.get(i + 1) {
+                Some(&command) if i + 2 == args.len() => {
+                    return (settings, Some(String::from(command)))
+                }
+                Some(_) => usage_error(&format!("{}: invalid argument", args[i + 2])),
+                None => usage_error("-c: option requires an argument"),
+            },
+            arg if options::is_option_word(arg) => match options::parse_word(&args[i..]) {
+                Ok((parsed, used)) => {
+                    settings.extend(parsed);
+                    i += used;
+                }
+                Err(msg) => usage_error(&msg),
+            },
+            arg => usage_error(&format!("{}: invalid argument", arg)),
+        }
+    }
+    (settings, None)
+}
 
 fn main() {
     env_logger::init();
     info!("Initializing shell");
-    let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) > 0 };
+    let (settings, command) = parse_args();
+    let interactive = command.is_none() && unsafe { libc::isatty(libc::STDIN_FILENO) > 0 };
     let state = State::new("frish", interactive);
+    for (opt, on) in settings {
+        state.set_option(opt, on);
+    }
+    if interactive {
+        if let Err(err) = state.load_history() {
+            eprintln!("Error: Cannot load history: {}", err);
+        }
+    }
     // run
-    read_eval_loop(&state);
+    match command {
+        Some(command) => {
+            frish::eval(&state, &command);
+        }
+        None => read_eval_loop(&state),
+    }
     // done
     info!("Finalizing shell");
-    exit(state.status.get().code());
+    frish::run_exit_trap(&state);
+    state.run_cleanup();
+    exit(state.status().code());
 }
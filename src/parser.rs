@@ -1,90 +1,377 @@
-use crate::common::Command;
-
-pub fn tokenize(line: &str) -> Vec<&str> {
-    let mut tokens = Vec::new();
-    let mut pos = 0;
-    let mut iter = line.chars();
-    let mut ch = iter.next();
-    loop {
+use std::fmt;
+use std::os::unix::io::RawFd;
+
+use crate::common::{Command, Group, GroupKind, Node, Redirect, Target};
+
+/// Token of the command line given by its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offsets of the token text, quotes excluded.
+    pub start: usize,
+    pub end: usize,
+    /// Quote of a quoted token.
+    pub quote: Option<char>,
+    /// Is the quote closed.
+    pub closed: bool,
+}
+
+// characters which are tokens of their own unless quoted
+fn is_operator(ch: char) -> bool {
+    matches!(ch, ';' | '(' | ')' | '\n')
+}
+
+/// Positions of the tokens in the line, the operators `;`, `(`, `)` and
+/// the newline are tokens of their own.
+pub fn spans(line: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut iter = line.char_indices().peekable();
+    while let Some((start, ch)) = iter.next() {
         match ch {
+            ch if is_operator(ch) => spans.push(Span {
+                start,
+                end: start + ch.len_utf8(),
+                quote: None,
+                closed: true,
+            }),
             // skip whitespace
-            Some(ch1) if ch1.is_whitespace() => {
-                pos += 1;
-                ch = iter.next();
-                continue;
-            }
+            ch if ch.is_whitespace() => continue,
             // single or double quotes
-            Some('"') | Some('\'') => {
-                pos += 1;
-                let token_start = pos;
-                let mut ch1 = iter.next();
-                loop {
-                    match ch1 {
-                        Some(ch2) => {
-                            pos += 1;
-                            ch1 = iter.next();
-                            if ch2 == ch.unwrap() {
-                                break;
-                            }
-                        }
-                        None => break,
-                    };
-                }
-                tokens.push(&line[token_start..pos - 1]);
+            '"' | '\'' => {
+                let end = iter.by_ref().find(|&(_, ch2)| ch2 == ch).map(|(i, _)| i);
+                spans.push(Span {
+                    start: start + 1,
+                    end: end.unwrap_or(line.len()),
+                    quote: Some(ch),
+                    closed: end.is_some(),
+                });
             }
             // word
-            Some(_) => {
-                let token_start = pos;
-                loop {
-                    pos += 1;
-                    ch = iter.next();
-                    match ch {
-                        Some(ch1) if ch1.is_whitespace() => break,
-                        Some(_) => continue,
-                        None => break,
-                    };
+            _ => {
+                let mut end = line.len();
+                while let Some(&(i, ch2)) = iter.peek() {
+                    if ch2.is_whitespace() || is_operator(ch2) {
+                        end = i;
+                        break;
+                    }
+                    iter.next();
                 }
-                tokens.push(&line[token_start..pos]);
+                spans.push(Span {
+                    start,
+                    end,
+                    quote: None,
+                    closed: true,
+                });
             }
-            None => break,
         }
-        pos += 1;
-        ch = iter.next();
     }
-    return tokens;
+    spans
+}
+
+/// Does the command continue on the next line: a quoted token is not closed,
+/// the line ends with a backslash or a group is not closed.
+pub fn is_continued(line: &str) -> bool {
+    spans(line).last().is_some_and(|span| !span.closed)
+        || line.trim_end_matches('\n').ends_with('\\')
+        || matches!(parse_list(line), Err(ParseError::Incomplete))
 }
 
-pub fn parse(line: &str) -> Option<Command> {
-    let tokens = tokenize(&line);
-    if tokens.len() == 0 {
-        None
+/// Quote the text so that the parser reads it back as one word, e.g. in the
+/// commands printed by `trap -p` and `complete`.
+pub fn quote(text: &str) -> String {
+    if text.contains('\'') {
+        format!("\"{}\"", text)
     } else {
-        let mut last = tokens.len() - 1;
-        // check for background
-        let back = tokens[last] == "&";
-        if back {
-            last -= 1;
+        format!("'{}'", text)
+    }
+}
+
+// descriptor number of a redirection
+fn fd_number(text: &str) -> Option<RawFd> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Redirection of the token: `[N]<FILE`, `[N]>FILE`, `[N]>|FILE`, `[N]>>FILE`,
+/// `[N]<&M`, `[N]>&M`, `[N]<&-` or `[N]>&-`. The descriptor N is 0 for `<` and 1
+/// for `>` by default; an empty FILE is given by the next token.
+pub fn redirect(token: &str) -> Option<Redirect<'_>> {
+    let digits = token.find(|ch: char| !ch.is_ascii_digit())?;
+    let (number, rest) = token.split_at(digits);
+    let (input, rest) = match rest.strip_prefix('<') {
+        Some(rest) => (true, rest),
+        None => (false, rest.strip_prefix('>')?),
+    };
+    let fd = match number {
+        "" if input => 0,
+        "" => 1,
+        _ => fd_number(number)?,
+    };
+    let target = match rest.strip_prefix('&') {
+        Some("-") => Target::Close,
+        Some(dup) => Target::Dup(fd_number(dup)?),
+        None if input => Target::Read(rest),
+        None => match (rest.strip_prefix('>'), rest.strip_prefix('|')) {
+            (Some(file), _) => Target::Append(file),
+            (_, Some(file)) => Target::Clobber(file),
+            _ => Target::Write(rest),
+        },
+    };
+    Some(Redirect { fd, target })
+}
+
+// the file of the redirection is the next token
+fn needs_file(redirect: &Redirect) -> bool {
+    matches!(
+        redirect.target,
+        Target::Read("") | Target::Write("") | Target::Clobber("") | Target::Append("")
+    )
+}
+
+/// What the token means in the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Command,
+    Argument,
+    InRedirect,
+    OutRedirect,
+    Background,
+    /// `;`, newline, `(`, `)` or the words `{` and `}` of a group.
+    Operator,
+}
+
+/// Roles of the tokens: the command (the first word which is not a redirection),
+/// its arguments and the redirections among them, `&` after a command and the
+/// operators separating or grouping the commands. Quoted tokens are words.
+pub fn roles(line: &str, spans: &[Span]) -> Vec<Role> {
+    let mut roles = vec![Role::Argument; spans.len()];
+    // the current command has its name, or a group has ended
+    let mut command = false;
+    let mut i = 0;
+    while i < spans.len() {
+        let text = &line[spans[i].start..spans[i].end];
+        let word = spans[i].quote.is_none();
+        let mut step = 1;
+        roles[i] = match text {
+            ";" | "\n" | "(" if word => {
+                command = false;
+                Role::Operator
+            }
+            ")" if word => {
+                command = true;
+                Role::Operator
+            }
+            "&" if word => {
+                command = false;
+                Role::Background
+            }
+            "{" if word && !command => Role::Operator,
+            "}" if word && !command => {
+                command = true;
+                Role::Operator
+            }
+            _ if word && redirect(text).is_some() => {
+                let role = match text.trim_start_matches(|ch: char| ch.is_ascii_digit()) {
+                    input if input.starts_with('<') => Role::InRedirect,
+                    _ => Role::OutRedirect,
+                };
+                // the file is the next word
+                if redirect(text).is_some_and(|redirect| needs_file(&redirect))
+                    && spans
+                        .get(i + 1)
+                        .is_some_and(|next| !is_separator(line, next))
+                {
+                    roles[i + 1] = role;
+                    step = 2;
+                }
+                role
+            }
+            _ if !command => {
+                command = true;
+                Role::Command
+            }
+            _ => Role::Argument,
+        };
+        i += step;
+    }
+    roles
+}
+
+// the unquoted operator or `&`
+fn is_separator(line: &str, span: &Span) -> bool {
+    let text = &line[span.start..span.end];
+    span.quote.is_none() && (text == "&" || text.starts_with(is_operator))
+}
+
+/// Error of parsing a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line ends inside a group.
+    Incomplete,
+    /// The token is not allowed at its place.
+    Unexpected(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Incomplete => write!(f, "syntax error: unexpected end of input"),
+            ParseError::Unexpected(token) => {
+                write!(f, "syntax error near unexpected token '{}'", token)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    line: &'a str,
+    spans: Vec<Span>,
+    roles: Vec<Role>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(line: &'a str) -> Parser<'a> {
+        let spans = spans(line);
+        let roles = roles(line, &spans);
+        Parser {
+            line,
+            spans,
+            roles,
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<(&'a str, Role)> {
+        let span = self.spans.get(self.pos)?;
+        Some((&self.line[span.start..span.end], self.roles[self.pos]))
+    }
+
+    // commands until the end of the line or the end of the group
+    fn list(&mut self, end: Option<&str>) -> Result<Vec<Node<'a>>, ParseError> {
+        let mut list = Vec::new();
+        while let Some((text, role)) = self.peek() {
+            match role {
+                Role::Operator if Some(text) == end => {
+                    self.pos += 1;
+                    return Ok(list);
+                }
+                Role::Operator if text == ";" || text == "\n" => self.pos += 1,
+                Role::Operator if text == "(" || text == "{" => {
+                    self.pos += 1;
+                    list.push(Node::Group(self.group(text)?));
+                }
+                Role::Operator => return Err(ParseError::Unexpected(String::from(text))),
+                // a lone `&`
+                Role::Background => self.pos += 1,
+                _ => {
+                    let cmd = self.command();
+                    // no command, e.g. only redirections
+                    if !cmd.args.is_empty() {
+                        list.push(Node::Command(cmd));
+                    }
+                }
+            }
+        }
+        match end {
+            Some(_) => Err(ParseError::Incomplete),
+            None => Ok(list),
         }
-        // check for output redirection
-        let out = if tokens[last].starts_with(">") {
-            last -= 1;
-            Some(&tokens[last + 1][1..])
-        } else {
-            None
+    }
+
+    // the redirection at the position with its file
+    fn redirect(&mut self) -> Redirect<'a> {
+        let (text, role) = self.peek().unwrap();
+        let mut redirect = redirect(text).unwrap();
+        self.pos += 1;
+        if needs_file(&redirect) {
+            // the next word with the same role is the file
+            if let Some((file, _)) = self.peek().filter(|&(_, next)| next == role) {
+                self.pos += 1;
+                redirect.target = match redirect.target {
+                    Target::Read(_) => Target::Read(file),
+                    Target::Append(_) => Target::Append(file),
+                    Target::Clobber(_) => Target::Clobber(file),
+                    _ => Target::Write(file),
+                };
+            }
+        }
+        redirect
+    }
+
+    // words and redirections of a simple command, with `&` after it
+    fn command(&mut self) -> Command<'a> {
+        let mut cmd = Command {
+            args: Vec::new(),
+            background: false,
+            redirects: Vec::new(),
+        };
+        while let Some((text, role)) = self.peek() {
+            match role {
+                Role::Command | Role::Argument => {
+                    cmd.args.push(text);
+                    self.pos += 1;
+                }
+                Role::InRedirect | Role::OutRedirect => {
+                    let redirect = self.redirect();
+                    cmd.redirects.push(redirect);
+                }
+                Role::Background => {
+                    cmd.background = true;
+                    self.pos += 1;
+                    break;
+                }
+                Role::Operator => break,
+            }
+        }
+        cmd
+    }
+
+    // the group after its opening token, with its redirections and `&`
+    fn group(&mut self, open: &str) -> Result<Group<'a>, ParseError> {
+        let (kind, end) = match open {
+            "(" => (GroupKind::Subshell, ")"),
+            _ => (GroupKind::Brace, "}"),
         };
-        // check for input redirection
-        let inr = if tokens[last].starts_with("<") {
-            last -= 1;
-            Some(&tokens[last + 1][1..])
-        } else {
-            None
+        let list = self.list(Some(end))?;
+        let mut group = Group {
+            kind,
+            list,
+            redirects: Vec::new(),
+            background: false,
         };
-        // return
-        Some(Command {
-            args: tokens[..last + 1].to_vec(),
-            background: back,
-            inredirect: inr,
-            outredirect: out,
-        })
+        while let Some((text, role)) = self.peek() {
+            match role {
+                Role::InRedirect | Role::OutRedirect => {
+                    let redirect = self.redirect();
+                    group.redirects.push(redirect);
+                }
+                Role::Background => {
+                    group.background = true;
+                    self.pos += 1;
+                    break;
+                }
+                Role::Operator if text == ";" || text == "\n" || text == ")" || text == "}" => {
+                    break
+                }
+                _ => return Err(ParseError::Unexpected(String::from(text))),
+            }
+        }
+        Ok(group)
+    }
+}
+
+/// Parse the commands of the line separated by `;` or newlines, with the
+/// groups `( LIST )` run in a subshell and `{ LIST; }` run in the shell.
+pub fn parse_list(line: &str) -> Result<Vec<Node<'_>>, ParseError> {
+    Parser::new(line).list(None)
+}
+
+/// Parse the first simple command of the line.
+pub fn parse(line: &str) -> Option<Command<'_>> {
+    match parse_list(line).ok()?.into_iter().next()? {
+        Node::Command(cmd) => Some(cmd),
+        Node::Group(_) => None,
     }
 }
@@ -0,0 +1,63 @@
+// Standard output and error collected in memory while commands run.
+
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::unistd::{close, dup2};
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use crate::shell::redirect::save_fd;
+
+// in-memory file replacing the descriptor, the original one is kept aside
+struct Replaced {
+    fd: RawFd,
+    saved: RawFd,
+    file: File,
+}
+
+impl Replaced {
+    fn new(fd: RawFd, name: &CStr) -> io::Result<Replaced> {
+        let memfd = memfd_create(name, MemFdCreateFlag::MFD_CLOEXEC)?;
+        let file = unsafe { File::from_raw_fd(memfd) };
+        let saved = save_fd(fd)?;
+        if let Err(err) = dup2(memfd, fd) {
+            close(saved).ok();
+            return Err(err.into());
+        }
+        Ok(Replaced { fd, saved, file })
+    }
+
+    // put the original descriptor back and return what was written
+    fn restore(mut self) -> io::Result<Vec<u8>> {
+        dup2(self.saved, self.fd)?;
+        close(self.saved)?;
+        let mut data = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Run the function with the standard output and error going to memory
+/// instead of the descriptors 1 and 2, also in the child processes.
+/// Returns the result of the function with the output and the errors.
+pub fn capture<T, F: FnOnce() -> T>(f: F) -> io::Result<(T, Vec<u8>, Vec<u8>)> {
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    let out = Replaced::new(libc::STDOUT_FILENO, c"frish-stdout")?;
+    let err = match Replaced::new(libc::STDERR_FILENO, c"frish-stderr") {
+        Ok(err) => err,
+        Err(e) => {
+            out.restore()?;
+            return Err(e);
+        }
+    };
+    let result = f();
+    // the buffered output belongs to the captured part
+    io::stdout().flush().ok();
+    io::stderr().flush().ok();
+    let stderr = err.restore();
+    let stdout = out.restore()?;
+    Ok((result, stdout, stderr?))
+}
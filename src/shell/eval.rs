@@ -1,15 +1,19 @@
 use std::io;
 
-use crate::common::report_error;
+use crate::common::{report_error, GroupKind, Node};
+use crate::editor::Editor;
 use crate::parser;
-use crate::shell::exec;
+use crate::shell::options::ShellOption;
+use crate::shell::{exec, prompt, trap};
 use crate::state::State;
 
-pub fn eval(state: &State, cmdstr: &str) {
-    if let Some(cmd) = parser::parse(&cmdstr) {
-        let res = match state.builtins.find(cmd.args[0]) {
-            Some(builtin) => exec::run_builtin(builtin, state, &cmd),
-            None => exec::run_external(state, &cmd),
+/// Run the commands of the list until its end or `exit`, with errexit until
+/// a command fails.
+pub fn run_list(state: &State, list: &[Node]) {
+    for node in list {
+        let res = match node {
+            Node::Command(cmd) => exec::run_command(state, cmd),
+            Node::Group(group) => exec::run_group(state, group),
         };
         match res {
             Ok(status) => state.set_status(&status),
@@ -18,16 +22,93 @@ pub fn eval(state: &State, cmdstr: &str) {
                 report_error(&err);
             }
         }
-    } else {
-        debug!("No command given.");
+        if !state.running.get() {
+            return;
+        }
+        if state.status.get().code() != 0 {
+            // the failed command of a brace group has run the trap already,
+            // a subshell has no ERR trap of its own
+            if !matches!(node, Node::Group(group) if group.kind == GroupKind::Brace) {
+                trap::run_err(state);
+            }
+            if state.option(ShellOption::Errexit) {
+                state.terminate();
+                return;
+            }
+        }
+        trap::run_pending(state);
+        if !state.running.get() {
+            return;
+        }
+    }
+}
+
+pub fn eval(state: &State, cmdstr: &str) {
+    match parser::parse_list(cmdstr) {
+        Ok(list) if list.is_empty() => debug!("No command given."),
+        // noexec only checks the syntax of scripts
+        Ok(_) if state.option(ShellOption::Noexec) && !state.interactive => {}
+        Ok(list) => run_list(state, &list),
+        Err(err) => {
+            eprintln!("frish: {}", err);
+            state.set_status_code(exec::SYNTAX_ERROR);
+        }
     }
 }
 
-pub fn read_eval(state: &State) {
+// read a command, continued on the following lines if incomplete
+fn read_command(state: &State, editor: &mut Editor) -> io::Result<Option<String>> {
+    if state.interactive {
+        let (ps1, ps2) = (prompt::primary(state), prompt::secondary(state));
+        return editor.read_line(state, &ps1, &ps2);
+    }
     let mut line = String::new();
-    match io::stdin().read_line(&mut line) {
-        Ok(0) => state.terminate(),
-        Ok(_) => eval(state, &line),
+    loop {
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(if line.is_empty() { None } else { Some(line) });
+        }
+        if !parser::is_continued(&line) {
+            return Ok(Some(line));
+        }
+    }
+}
+
+// expand the history references and remember the command
+fn interactive_command(state: &State, line: String) -> Option<String> {
+    let expanded = state.history.borrow().expand(&line);
+    let line = match expanded {
+        Ok(Some(expanded)) => {
+            println!("{}", expanded.trim_end());
+            expanded
+        }
+        Ok(None) => line,
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            state.set_status_code(1);
+            return None;
+        }
+    };
+    if let Err(err) = state.add_history(&line) {
+        eprintln!("Error: Cannot save history: {}", err);
+    }
+    Some(line)
+}
+
+pub fn read_eval(state: &State, editor: &mut Editor) {
+    match read_command(state, editor) {
+        Ok(None) => state.terminate(),
+        Ok(Some(line)) => {
+            let line = if state.interactive {
+                match interactive_command(state, line) {
+                    Some(line) => line,
+                    None => return,
+                }
+            } else {
+                line
+            };
+            // a backslash joins the lines
+            eval(state, &line.replace("\\\n", ""));
+        }
         Err(err) => {
             state.set_status_code(nix::errno::errno());
             report_error(&err);
@@ -35,9 +116,34 @@ pub fn read_eval(state: &State) {
     }
 }
 
+/// Evaluate the commands of the text line by line, until the end or `exit`.
+pub fn eval_text(state: &State, text: &str) {
+    let mut command = String::new();
+    for line in text.split_inclusive('\n') {
+        command.push_str(line);
+        if parser::is_continued(&command) {
+            continue;
+        }
+        eval(state, &command.replace("\\\n", ""));
+        command.clear();
+        if !state.running.get() {
+            return;
+        }
+    }
+    // an incomplete command at the end
+    if !command.is_empty() {
+        eval(state, &command.replace("\\\n", ""));
+    }
+}
+
 pub fn read_eval_loop(state: &State) {
+    let mut editor = Editor::new();
     while state.running.get() {
-        exec::print_prompt(&state);
-        read_eval(&state);
+        state.reap_jobs();
+        trap::run_pending(state);
+        if !state.running.get() {
+            break;
+        }
+        read_eval(state, &mut editor);
     }
 }
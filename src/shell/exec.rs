@@ -1,35 +1,44 @@
 use log::{debug, info};
 use nix::{
+    errno::Errno,
     sys::wait::{waitpid, WaitStatus},
-    unistd::{execvp, fork, ForkResult, Pid},
+    unistd::{self, execv, fork, AccessFlags, ForkResult, Pid},
+};
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    path::Path,
 };
-use std::{convert::Infallible, io, io::Write};
 
 use crate::builtins::Builtin;
 use crate::common::report_error;
-use crate::common::Command;
-use crate::shell::redirect::{redirect_stdin, redirect_stdout, restore_stdin, restore_stdout};
+use crate::common::{Command, Group, GroupKind};
+use crate::shell::eval::{eval_text, run_list};
+use crate::shell::options::ShellOption;
+use crate::shell::redirect::redirect;
+use crate::shell::resolve::{self, resolve, Resolved};
+use crate::shell::trap;
 use crate::state::{State, Status};
 
-pub fn print_prompt(state: &State) {
-    if state.interactive {
-        print!("{}> ", state.name.borrow());
-        io::stdout().flush().expect("Cannot flush stdout");
-    }
-}
-
 pub fn wait_process(pid: Pid) -> io::Result<Status> {
     debug!("Waiting for {}.\n", pid);
     match waitpid(pid, None)? {
         WaitStatus::Exited(_, code) => Ok(Status::from_code(code)),
+        // killed by the signal, e.g. SIGPIPE in a pipeline
+        WaitStatus::Signaled(_, sig, _) => Ok(Status::from_code(128 + sig as i32)),
         _ => Err(io::Error::last_os_error()),
     }
 }
 
 pub fn fork_child_wait<F: FnMut()>(child: &mut F) -> io::Result<Status> {
+    // the buffered output must not be written twice or late
+    io::stdout().flush()?;
     match unsafe { fork()? } {
         ForkResult::Parent { child } => wait_process(child),
         ForkResult::Child => {
+            trap::forget();
             child();
             std::process::exit(127);
         }
@@ -37,73 +46,247 @@ pub fn fork_child_wait<F: FnMut()>(child: &mut F) -> io::Result<Status> {
 }
 
 pub fn fork_child<F: FnMut()>(child: &mut F) -> io::Result<Pid> {
+    // the buffered output must not be written twice or late
+    io::stdout().flush()?;
     match unsafe { fork()? } {
         ForkResult::Parent { child } => Ok(child),
         ForkResult::Child => {
+            trap::forget();
             child();
             std::process::exit(0);
         }
     }
 }
 
-fn exec_external(cmd: &Command) -> Result<Infallible, io::Error> {
-    redirect_stdin(cmd.inredirect)
-        .and_then(|_| redirect_stdout(cmd.outredirect))
-        .and_then(|_| {
-            let args = &cmd.args;
-            let args = args
-                .into_iter()
-                .map(|&arg| std::ffi::CString::new(arg))
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap();
-            let cprog = &args[0];
-            //cmd.args[0].with_nix_path(|cprog| execvp(cprog, args.as_ref().unwrap()))
-            execvp(cprog, &args);
-            Err(std::io::Error::last_os_error())
-        })
+/// Status of a command line with a syntax error.
+pub const SYNTAX_ERROR: i32 = 2;
+
+/// Status of a command which is not found.
+pub const NOT_FOUND: i32 = 127;
+
+/// Status of a command which is found but cannot be executed.
+pub const NOT_EXECUTABLE: i32 = 126;
+
+// report why the command cannot run and return the status
+fn exec_failure(name: &str, msg: &str, code: i32) -> Status {
+    eprintln!("frish: {}: {}", name, msg);
+    Status::from_code(code)
+}
+
+// the file can be executed, checked before forking
+fn check_executable(path: &Path) -> Result<(), (&'static str, i32)> {
+    match fs::metadata(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(("No such file or directory", NOT_FOUND))
+        }
+        Err(_) => Err(("permission denied", NOT_EXECUTABLE)),
+        Ok(meta) if meta.is_dir() => Err(("is a directory", NOT_EXECUTABLE)),
+        Ok(_) if unistd::access(path, AccessFlags::X_OK).is_err() => {
+            Err(("permission denied", NOT_EXECUTABLE))
+        }
+        Ok(_) => Ok(()),
+    }
+}
+
+// the interpreter named on the #! line of the script
+fn interpreter(path: &Path) -> Option<String> {
+    let mut head = [0; 256];
+    let len = File::open(path)
+        .and_then(|mut file| file.read(&mut head))
+        .ok()?;
+    let line = head[..len]
+        .strip_prefix(b"#!")?
+        .split(|&b| b == b'\n')
+        .next()?;
+    let line = String::from_utf8_lossy(line);
+    line.split_whitespace().next().map(String::from)
+}
+
+// run the script without #! by a subshell in the child, returns its status
+fn run_script(state: &State, path: &Path) -> i32 {
+    let name = path.to_string_lossy();
+    let text = match fs::read(path) {
+        Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+        Err(err) => return exec_failure(&name, &err.to_string(), NOT_EXECUTABLE).code(),
+    };
+    let substate = state.sub();
+    eval_text(&substate, &text);
+    trap::run_exit(&substate);
+    substate.run_cleanup();
+    substate.status.get().code()
+}
+
+// replace the child process by the program, returns the exit status if it fails
+fn exec_external(state: &State, cmd: &Command, path: &Path, args: &[CString]) -> i32 {
+    // the child is replaced, the redirections are not restored
+    match redirect(&cmd.redirects, state.option(ShellOption::Noclobber)) {
+        Ok(saved) => saved.keep(),
+        Err(err) => {
+            report_error(&err);
+            return 1;
+        }
+    }
+    let name = cmd.args[0];
+    let cpath = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(cpath) => cpath,
+        Err(_) => return exec_failure(name, "invalid path", NOT_EXECUTABLE).code(),
+    };
+    let Err(err) = execv(&cpath, args);
+    let status = match err {
+        // not a binary nor a script with #!, frish runs it
+        Errno::ENOEXEC => return run_script(state, path),
+        Errno::ENOENT | Errno::EACCES => match interpreter(path) {
+            Some(interpreter) => {
+                let msg = format!("{}: bad interpreter: {}", interpreter, err.desc());
+                exec_failure(&path.to_string_lossy(), &msg, NOT_EXECUTABLE)
+            }
+            None => exec_failure(name, "permission denied", NOT_EXECUTABLE),
+        },
+        err => exec_failure(name, err.desc(), NOT_EXECUTABLE),
+    };
+    status.code()
+}
+
+// the arguments for exec, a failure is reported
+fn c_args(cmd: &Command) -> Result<Vec<CString>, Status> {
+    cmd.args
+        .iter()
+        .map(|&arg| CString::new(arg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| exec_failure(cmd.args[0], "argument contains a null byte", NOT_EXECUTABLE))
 }
 
-pub fn run_external(state: &State, cmd: &Command) -> io::Result<Status> {
+/// Replace the shell process by the external command, the builtins are skipped.
+/// Returns the status if the command cannot run.
+pub fn exec_command(state: &State, cmd: &Command) -> Status {
+    let name = cmd.args[0];
+    let path = match resolve::find_file(state, name, true) {
+        Some(path) => path,
+        None => return exec_failure(name, "command not found", NOT_FOUND),
+    };
+    if let Err((msg, code)) = check_executable(&path) {
+        return exec_failure(name, msg, code);
+    }
+    match c_args(cmd) {
+        Ok(args) => Status::from_code(exec_external(state, cmd, &path, &args)),
+        Err(status) => status,
+    }
+}
+
+/// Run the executable file with the arguments of the command.
+pub fn run_external(state: &State, cmd: &Command, path: &Path) -> io::Result<Status> {
     debug!("Running external command: '{}'", cmd.args[0]);
+    let args = match c_args(cmd) {
+        Ok(args) => args,
+        Err(status) => return Ok(status),
+    };
     if cmd.background {
-        state.lastpid.set(
-            fork_child(&mut || {
-                if let Err(err) = exec_external(cmd) {
-                    report_error(&err);
-                }
-            })
-            .unwrap(),
-        );
+        let pid = fork_child(&mut || {
+            std::process::exit(exec_external(state, cmd, path, &args));
+        })?;
+        state.add_job(pid);
         Ok(Status::success())
     } else {
         fork_child_wait(&mut || {
-            if let Err(err) = exec_external(cmd) {
-                report_error(&err);
-            }
+            std::process::exit(exec_external(state, cmd, path, &args));
         })
     }
 }
 
 fn exec_builtin(state: &State, builtin: &Builtin, cmd: &Command) -> io::Result<Status> {
-    let fdinold = redirect_stdin(cmd.inredirect)?;
-    let fdoutold = redirect_stdout(cmd.outredirect).or_else(|err| {
-        restore_stdin(fdinold);
-        Err(err)
-    })?;
-    let status = (builtin.handler)(state, &cmd.args)?;
-    restore_stdin(fdinold);
-    restore_stdout(fdoutold);
-    Ok(status)
+    let saved = match redirect(&cmd.redirects, state.option(ShellOption::Noclobber)) {
+        Ok(saved) => saved,
+        Err(err) => {
+            report_error(&err);
+            return Ok(Status::from_code(1));
+        }
+    };
+    let status = builtin.run(state, &cmd.args);
+    // `exec` without a command keeps its redirections
+    if state.keep_redirects.replace(false) {
+        saved.keep();
+    } else {
+        saved.restore();
+    }
+    status
 }
 
 pub fn run_builtin(builtin: &Builtin, state: &State, cmd: &Command) -> io::Result<Status> {
     info!("Running builtin command: '{}'", cmd.args[0]);
     if cmd.background {
-        state.lastpid.set(fork_child(&mut || {
+        let pid = fork_child(&mut || {
             exec_builtin(state, builtin, cmd).unwrap();
-        })?);
+        })?;
+        state.add_job(pid);
         Ok(Status::success())
     } else {
         exec_builtin(state, builtin, cmd)
     }
 }
+
+/// Run the command by the builtin or the file its name resolves to.
+/// With xtrace the command is printed first, after `$PS4`.
+pub fn run_command(state: &State, cmd: &Command) -> io::Result<Status> {
+    if state.option(ShellOption::Xtrace) {
+        let ps4 = state.get_var("PS4").unwrap_or_else(|| String::from("+ "));
+        eprintln!("{}{}", ps4, cmd.args.join(" "));
+    }
+    match resolve(state, cmd.args[0]) {
+        Resolved::Builtin(builtin) => run_builtin(&builtin, state, cmd),
+        Resolved::File(path) => match check_executable(&path) {
+            Ok(()) => run_external(state, cmd, &path),
+            Err((msg, code)) => Ok(exec_failure(cmd.args[0], msg, code)),
+        },
+        Resolved::NotFound => Ok(exec_failure(cmd.args[0], "command not found", NOT_FOUND)),
+    }
+}
+
+// the commands of the group in a child, returns the exit status
+fn exec_group(state: &State, group: &Group) -> i32 {
+    match redirect(&group.redirects, state.option(ShellOption::Noclobber)) {
+        Ok(saved) => saved.keep(),
+        Err(err) => {
+            report_error(&err);
+            return 1;
+        }
+    }
+    match group.kind {
+        GroupKind::Subshell => {
+            let substate = state.sub();
+            run_list(&substate, &group.list);
+            trap::run_exit(&substate);
+            substate.run_cleanup();
+            substate.status.get().code()
+        }
+        GroupKind::Brace => {
+            run_list(state, &group.list);
+            state.status.get().code()
+        }
+    }
+}
+
+/// Run the group: a subshell or a group in the background in a child process,
+/// a brace group in the shell with its redirections restored after it.
+pub fn run_group(state: &State, group: &Group) -> io::Result<Status> {
+    if group.background {
+        let pid = fork_child(&mut || std::process::exit(exec_group(state, group)))?;
+        state.add_job(pid);
+        return Ok(Status::success());
+    }
+    if group.kind == GroupKind::Subshell {
+        return fork_child_wait(&mut || std::process::exit(exec_group(state, group)));
+    }
+    let saved = match redirect(&group.redirects, state.option(ShellOption::Noclobber)) {
+        Ok(saved) => saved,
+        Err(err) => {
+            report_error(&err);
+            // no command of the group has failed and run the ERR trap
+            state.set_status_code(1);
+            trap::run_err(state);
+            return Ok(Status::from_code(1));
+        }
+    };
+    run_list(state, &group.list);
+    saved.restore();
+    Ok(state.status.get())
+}
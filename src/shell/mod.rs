@@ -1,4 +1,9 @@
+pub mod capture;
 pub mod eval;
 pub mod exec;
+pub mod options;
 pub mod pipes;
+pub mod prompt;
 pub mod redirect;
+pub mod resolve;
+pub mod trap;
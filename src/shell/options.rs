@@ -0,0 +1,119 @@
+// Options of the shell switched by `set` or on the command line.
+
+/// Option changing how the shell runs commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellOption {
+    /// Exit after a failed command.
+    Errexit,
+    /// `>` does not overwrite existing files.
+    Noclobber,
+    /// Parse the commands without running them.
+    Noexec,
+    /// Unset variables are errors, no effect until variables are expanded.
+    Nounset,
+    /// The status of a pipeline is the one of its last failed command.
+    Pipefail,
+    /// Print the commands before running them.
+    Xtrace,
+}
+
+use ShellOption::*;
+
+impl ShellOption {
+    pub const ALL: [ShellOption; 6] = [Errexit, Noclobber, Noexec, Nounset, Pipefail, Xtrace];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Errexit => "errexit",
+            Noclobber => "noclobber",
+            Noexec => "noexec",
+            Nounset => "nounset",
+            Pipefail => "pipefail",
+            Xtrace => "xtrace",
+        }
+    }
+
+    /// The letter of the option in `-e` or `+e`.
+    pub fn letter(self) -> Option<char> {
+        match self {
+            Errexit => Some('e'),
+            Noclobber => Some('C'),
+            Noexec => Some('n'),
+            Nounset => Some('u'),
+            Pipefail => None,
+            Xtrace => Some('x'),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ShellOption> {
+        ShellOption::ALL
+            .iter()
+            .copied()
+            .find(|opt| opt.name() == name)
+    }
+
+    pub fn from_letter(letter: char) -> Option<ShellOption> {
+        ShellOption::ALL
+            .iter()
+            .copied()
+            .find(|opt| opt.letter() == Some(letter))
+    }
+}
+
+/// The set of the options which are on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options(u32);
+
+impl Options {
+    pub fn has(self, opt: ShellOption) -> bool {
+        self.0 & (1 << opt as u32) != 0
+    }
+
+    pub fn with(self, opt: ShellOption, on: bool) -> Options {
+        if on {
+            Options(self.0 | 1 << opt as u32)
+        } else {
+            Options(self.0 & !(1 << opt as u32))
+        }
+    }
+
+    /// The letters of the options which are on, e.g. `eux`.
+    pub fn letters(self) -> String {
+        ShellOption::ALL
+            .iter()
+            .filter(|&&opt| self.has(opt))
+            .filter_map(|opt| opt.letter())
+            .collect()
+    }
+}
+
+/// Is the word an option word of `set`, e.g. `-e`, `+x` or `-o`.
+pub fn is_option_word(word: &str) -> bool {
+    word.len() > 1 && (word.starts_with('-') || word.starts_with('+')) && word != "--"
+}
+
+/// Parse the option word at the start of the arguments: `-e`, `+eu`, `-o NAME`
+/// or `+o NAME` (`-` turns the options on, `+` off). Returns the options with
+/// their settings and the number of arguments used.
+pub fn parse_word(args: &[&str]) -> Result<(Vec<(ShellOption, bool)>, usize), String> {
+    let word = args[0];
+    let on = word.starts_with('-');
+    let mut settings = Vec::new();
+    let mut used = 1;
+    for letter in word.chars().skip(1) {
+        let opt = match letter {
+            'o' => {
+                let name = args
+                    .get(used)
+                    .ok_or_else(|| format!("{}: option requires an argument", word))?;
+                used += 1;
+                ShellOption::from_name(name)
+                    .ok_or_else(|| format!("{}: invalid option name", name))?
+            }
+            _ => ShellOption::from_letter(letter)
+                .ok_or_else(|| format!("{}{}: invalid option", &word[..1], letter))?,
+        };
+        settings.push((opt, on));
+    }
+    Ok((settings, used))
+}
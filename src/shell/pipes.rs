@@ -5,20 +5,25 @@ use crate::shell::eval::eval;
 use crate::shell::exec::fork_child;
 use crate::state::State;
 
-pub fn pipes_begin(state: &State, cmdstr: &str) -> io::Result<(RawFd, RawFd)> {
+pub fn pipes_begin(state: &State, cmdstr: &str) -> io::Result<((RawFd, RawFd), Pid)> {
     let fds = pipe()?;
-    fork_child(&mut || {
+    let pid = fork_child(&mut || {
         dup2(fds.1, 1).unwrap();
         close(fds.0).unwrap();
         close(fds.1).unwrap();
         eval(state, cmdstr);
+        std::process::exit(state.status.get().code());
     })?;
-    Ok(fds)
+    Ok((fds, pid))
 }
 
-pub fn pipes_cont(state: &State, cmdstr: &str, fds1: (RawFd, RawFd)) -> io::Result<(RawFd, RawFd)> {
+pub fn pipes_cont(
+    state: &State,
+    cmdstr: &str,
+    fds1: (RawFd, RawFd),
+) -> io::Result<((RawFd, RawFd), Pid)> {
     let fds2 = pipe()?;
-    fork_child(&mut || {
+    let pid = fork_child(&mut || {
         dup2(fds1.0, 0).unwrap();
         dup2(fds2.1, 1).unwrap();
         close(fds1.0).unwrap();
@@ -26,10 +31,11 @@ pub fn pipes_cont(state: &State, cmdstr: &str, fds1: (RawFd, RawFd)) -> io::Resu
         close(fds2.0).unwrap();
         close(fds2.1).unwrap();
         eval(state, cmdstr);
+        std::process::exit(state.status.get().code());
     })?;
     close(fds1.0).unwrap();
     close(fds1.1).unwrap();
-    Ok(fds2)
+    Ok((fds2, pid))
 }
 
 pub fn pipes_end(state: &State, cmdstr: &str, fds: (RawFd, RawFd)) -> io::Result<Pid> {
@@ -38,6 +44,7 @@ pub fn pipes_end(state: &State, cmdstr: &str, fds: (RawFd, RawFd)) -> io::Result
         close(fds.0).unwrap();
         close(fds.1).unwrap();
         eval(state, cmdstr);
+        std::process::exit(state.status.get().code());
     });
     close(fds.0).unwrap();
     close(fds.1).unwrap();
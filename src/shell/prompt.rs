@@ -0,0 +1,137 @@
+// Prompts given by PS1 and PS2 with bash like backslash escapes.
+
+use nix::unistd;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::format_time;
+use crate::state::State;
+
+/// Prompt used when PS1 is not set, the shell name can be changed by `name`.
+pub const DEFAULT_PS1: &str = "\\s> ";
+
+/// Continuation prompt used when PS2 is not set.
+pub const DEFAULT_PS2: &str = "> ";
+
+/// The primary prompt.
+pub fn primary(state: &State) -> String {
+    let template = state.get_var("PS1");
+    render(state, template.as_deref().unwrap_or(DEFAULT_PS1))
+}
+
+/// The prompt of the continuation lines.
+pub fn secondary(state: &State) -> String {
+    let template = state.get_var("PS2");
+    render(state, template.as_deref().unwrap_or(DEFAULT_PS2))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+fn user() -> String {
+    let uid = unistd::getuid();
+    match unistd::User::from_uid(uid) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+fn host() -> String {
+    let mut buf = [0u8; 256];
+    match unistd::gethostname(&mut buf) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Expand the escapes of the prompt, unknown or incomplete escapes are kept as they are:
+///
+/// * `\s` shell name, `\u` user, `\h`/`\H` host up to the first dot or the whole
+/// * `\w` current directory with `~` for the home, `\W` its last component
+/// * `\L` subshell depth, `\?` status of the last command, `\j` number of background jobs
+/// * `\t`/`\T` time in the 24/12-hour format, `\A` hours and minutes, `\d` date,
+///   `\D{FORMAT}` time formatted by strftime(3)
+/// * `\$` `#` for root, otherwise `$`
+/// * `\e` escape character starting ANSI colors, e.g. `\e[32m`, `\a` bell, `\n` newline,
+///   `\NNN` octal character code, `\\` backslash, `\[` and `\]` are ignored
+pub fn render(state: &State, template: &str) -> String {
+    let mut out = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        let (_, esc) = match chars.next() {
+            Some(next) => next,
+            None => {
+                out.push('\\');
+                break;
+            }
+        };
+        match esc {
+            's' => out.push_str(&state.name.borrow()),
+            'u' => out.push_str(&user()),
+            'h' => out.push_str(host().split('.').next().unwrap_or("")),
+            'H' => out.push_str(&host()),
+            'w' | 'W' => {
+                let pwd = state.get_var("PWD").unwrap_or_default();
+                let dir = state.tilde_dir(&pwd);
+                match dir.rsplit_once('/') {
+                    Some((_, last)) if esc == 'W' && !last.is_empty() => out.push_str(last),
+                    _ => out.push_str(&dir),
+                }
+            }
+            'L' => out.push_str(&state.depth.to_string()),
+            '?' => out.push_str(&state.status.get().code().to_string()),
+            'j' => out.push_str(&state.jobs.borrow().len().to_string()),
+            't' => out.push_str(&format_time(now(), "%H:%M:%S")),
+            'T' => out.push_str(&format_time(now(), "%I:%M:%S")),
+            'A' => out.push_str(&format_time(now(), "%H:%M")),
+            'd' => out.push_str(&format_time(now(), "%a %b %d")),
+            'D' => {
+                let rest = &template[i + 2..];
+                match rest.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+                    Some((format, _)) => {
+                        out.push_str(&format_time(now(), format));
+                        // skip the braces and the format
+                        for _ in 0..format.chars().count() + 2 {
+                            chars.next();
+                        }
+                    }
+                    None => out.push_str("\\D"),
+                }
+            }
+            '$' => out.push(if unistd::geteuid().is_root() {
+                '#'
+            } else {
+                '$'
+            }),
+            'e' => out.push('\x1b'),
+            'a' => out.push('\x07'),
+            'n' => out.push('\n'),
+            '\\' => out.push('\\'),
+            '[' | ']' => {}
+            '0'..='7' => {
+                let mut code = esc.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|&(_, ch)| ch.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            _ => {
+                out.push('\\');
+                out.push(esc);
+            }
+        }
+    }
+    out
+}
@@ -1,50 +1,159 @@
 use nix::{
-    fcntl::{open, OFlag},
-    sys::stat::Mode,
-    unistd::{close, dup, dup2},
+    errno::Errno,
+    fcntl::{fcntl, open, FcntlArg, OFlag},
+    sys::stat::{fstat, Mode, SFlag},
+    unistd::{close, dup2},
 };
-use std::{io, os::unix::io::RawFd};
-
-pub fn redirect_stdin(infile: Option<&str>) -> io::Result<Option<RawFd>> {
-    if let Some(infile) = infile {
-        debug!("Redirecting stdin to {}", infile);
-        let fd = open(infile, OFlag::O_RDONLY, Mode::empty())?;
-        let fdold = dup(0).unwrap();
-        dup2(fd, 0).unwrap();
-        close(fd).unwrap();
-        Ok(Some(fdold))
-    } else {
-        Ok(None)
+use std::{
+    io::{self, Write},
+    os::unix::io::RawFd,
+};
+
+use crate::common::{Redirect, Target, FILE_MODE};
+
+/// Descriptors from this one up belong to the shell itself, the lower ones
+/// are left to the user and the commands.
+pub const SHELL_FD: RawFd = 10;
+
+/// Copy of the descriptor among the descriptors of the shell, closed on exec.
+pub fn save_fd(fd: RawFd) -> io::Result<RawFd> {
+    Ok(fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(SHELL_FD))?)
+}
+
+fn bad_fd(fd: RawFd) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}: Bad file descriptor", fd),
+    )
+}
+
+// open the file for `>` with noclobber: a new file, or an existing one which
+// is not a regular file, e.g. /dev/null
+fn open_noclobber(file: &str) -> nix::Result<RawFd> {
+    let flag = OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_WRONLY;
+    match open(file, flag, FILE_MODE) {
+        Err(Errno::EEXIST) => {}
+        res => return res,
+    }
+    let fd = open(file, OFlag::O_WRONLY, Mode::empty())?;
+    match fstat(fd) {
+        Ok(st) if SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG => Ok(fd),
+        res => {
+            close(fd).ok();
+            Err(res.err().unwrap_or(Errno::EEXIST))
+        }
     }
 }
 
-pub fn restore_stdin(fdold: Option<RawFd>) {
-    if let Some(fdold) = fdold {
-        debug!("Restoring stdin");
-        dup2(fdold, 0).unwrap();
-        close(fdold).unwrap();
+// make the descriptor refer to the target
+fn apply(redirect: &Redirect, noclobber: bool) -> io::Result<()> {
+    let fd = redirect.fd;
+    let (file, flag) = match redirect.target {
+        Target::Read(file) => (file, OFlag::O_RDONLY),
+        Target::Write(file) if noclobber => {
+            let opened = open_noclobber(file).map_err(|err| match err {
+                Errno::EEXIST => io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{}: cannot overwrite existing file", file),
+                ),
+                err => open_error(file, err),
+            })?;
+            return replace_fd(opened, fd);
+        }
+        Target::Write(file) | Target::Clobber(file) => {
+            (file, OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC)
+        }
+        Target::Append(file) => (file, OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_APPEND),
+        Target::Dup(from) if from >= SHELL_FD => return Err(bad_fd(from)),
+        Target::Dup(from) => {
+            // the copied descriptor must be open
+            fcntl(from, FcntlArg::F_GETFD).map_err(|_| bad_fd(from))?;
+            if from != fd {
+                dup2(from, fd)?;
+            }
+            return Ok(());
+        }
+        Target::Close => {
+            match close(fd) {
+                Ok(()) | Err(Errno::EBADF) => {}
+                Err(err) => return Err(err.into()),
+            }
+            return Ok(());
+        }
+    };
+    let opened = open(file, flag, FILE_MODE).map_err(|err| open_error(file, err))?;
+    replace_fd(opened, fd)
+}
+
+fn open_error(file: &str, err: Errno) -> io::Error {
+    let kind = io::Error::from(err).kind();
+    io::Error::new(kind, format!("{}: {}", file, err.desc()))
+}
+
+// move the opened descriptor to fd
+fn replace_fd(opened: RawFd, fd: RawFd) -> io::Result<()> {
+    if opened != fd {
+        let res = dup2(opened, fd);
+        close(opened)?;
+        res?;
     }
+    Ok(())
 }
 
-pub fn redirect_stdout(outfile: Option<&str>) -> io::Result<Option<RawFd>> {
-    if let Some(outfile) = outfile {
-        debug!("Redirecting stdout to {}", outfile);
-        let flag = OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC;
-        let mode = Mode::S_IRUSR | Mode::S_IWUSR;
-        let fd = open(outfile, flag, mode)?;
-        let fdold = dup(1).unwrap();
-        dup2(fd, 1).unwrap();
-        close(fd).unwrap();
-        Ok(Some(fdold))
-    } else {
-        Ok(None)
+/// Descriptors replaced by redirections with their saved copies, None if
+/// the descriptor was closed.
+#[derive(Debug)]
+pub struct Saved(Vec<(RawFd, Option<RawFd>)>);
+
+impl Saved {
+    /// Make the descriptors refer to what they did before the redirections.
+    pub fn restore(self) {
+        io::stdout().flush().ok();
+        for &(fd, copy) in self.0.iter().rev() {
+            debug!("Restoring descriptor {}", fd);
+            match copy {
+                Some(copy) => {
+                    dup2(copy, fd).ok();
+                    close(copy).ok();
+                }
+                None => {
+                    close(fd).ok();
+                }
+            }
+        }
+    }
+
+    /// Keep the redirections for good and forget the saved copies.
+    pub fn keep(self) {
+        for copy in self.0.iter().filter_map(|&(_, copy)| copy) {
+            close(copy).ok();
+        }
     }
 }
 
-pub fn restore_stdout(fdold: Option<RawFd>) {
-    if let Some(fdold) = fdold {
-        debug!("Restoring stdout");
-        dup2(fdold, 1).unwrap();
-        close(fdold).unwrap();
+/// Apply the redirections in order, the descriptors they replace are saved
+/// for `Saved::restore`. On an error the applied ones are restored. With
+/// `noclobber` the `>` redirections do not overwrite existing files.
+pub fn redirect(redirects: &[Redirect], noclobber: bool) -> io::Result<Saved> {
+    let mut saved = Saved(Vec::new());
+    if !redirects.is_empty() {
+        io::stdout().flush()?;
+    }
+    for redirect in redirects {
+        debug!("Redirecting {:?}", redirect);
+        let fd = redirect.fd;
+        if fd >= SHELL_FD {
+            saved.restore();
+            return Err(bad_fd(fd));
+        }
+        if !saved.0.iter().any(|&(old, _)| old == fd) {
+            // a closed descriptor is closed again
+            saved.0.push((fd, save_fd(fd).ok()));
+        }
+        if let Err(err) = apply(redirect, noclobber) {
+            saved.restore();
+            return Err(err);
+        }
     }
+    Ok(saved)
 }
@@ -0,0 +1,83 @@
+// Resolution of command names to builtins and executable files.
+
+use nix::unistd::{self, AccessFlags};
+use std::path::{Path, PathBuf};
+
+use crate::builtins::Builtin;
+use crate::state::{Hashed, State};
+
+/// What a command name runs.
+#[derive(Debug, Clone)]
+pub enum Resolved<'a> {
+    Builtin(Builtin<'a>),
+    File(PathBuf),
+    NotFound,
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file() && unistd::access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// The executable files of the name in the directories of PATH, in order.
+pub fn search_path(state: &State, name: &str) -> Vec<PathBuf> {
+    let path = state.get_var("PATH").unwrap_or_default();
+    path.split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(name))
+        .filter(|path| is_executable(path))
+        .collect()
+}
+
+/// The remembered path of the command, unless the file is gone since.
+pub fn hashed(state: &State, name: &str) -> Option<PathBuf> {
+    let mut hash = state.hash.borrow_mut();
+    match hash.get(name) {
+        Some(hashed) if is_executable(&hashed.path) => Some(hashed.path.clone()),
+        Some(_) => {
+            hash.remove(name);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Search PATH for the command and remember the path in the hash table.
+pub fn hash(state: &State, name: &str) -> Option<PathBuf> {
+    let path = search_path(state, name).into_iter().next()?;
+    let hashed = Hashed {
+        path: path.clone(),
+        hits: 0,
+    };
+    state.hash.borrow_mut().insert(String::from(name), hashed);
+    Some(path)
+}
+
+/// The file run by the command name: the name itself if it contains a slash,
+/// otherwise the hashed path or the first one found on PATH. Only with `remember`
+/// the path is added to the hash table and its use counted.
+pub fn find_file(state: &State, name: &str, remember: bool) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+    if !remember {
+        return hashed(state, name).or_else(|| search_path(state, name).into_iter().next());
+    }
+    if hashed(state, name).is_none() {
+        hash(state, name)?;
+    }
+    let mut table = state.hash.borrow_mut();
+    let hashed = table.get_mut(name)?;
+    hashed.hits += 1;
+    Some(hashed.path.clone())
+}
+
+/// Resolve the command name as it is run: an enabled builtin first, then a file.
+pub fn resolve<'a>(state: &State<'a>, name: &str) -> Resolved<'a> {
+    if let Some(builtin) = state.builtins.borrow().find(name) {
+        return Resolved::Builtin(builtin.clone());
+    }
+    match find_file(state, name, true) {
+        Some(path) => Resolved::File(path),
+        None => Resolved::NotFound,
+    }
+}
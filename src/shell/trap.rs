@@ -0,0 +1,205 @@
+// Traps: commands run on signals, on the exit of the shell and on failures.
+//
+// The signal handler only writes the signal number to a pipe, the commands
+// run later from the pipe at a safe point between two commands.
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd;
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+
+use crate::cleanup;
+use crate::shell::eval::eval;
+use crate::shell::redirect::save_fd;
+use crate::state::State;
+
+/// When a trap runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Condition {
+    /// The shell exits.
+    Exit,
+    /// The shell gets the signal.
+    Signal(Signal),
+    /// A command fails.
+    Err,
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// The condition by its name, e.g. `EXIT`, `INT` or `SIGINT`, or the signal number.
+    fn from_str(name: &str) -> Result<Self, String> {
+        let upper = name.to_ascii_uppercase();
+        let signal = match upper.as_str() {
+            "EXIT" | "0" => return Ok(Condition::Exit),
+            "ERR" => return Ok(Condition::Err),
+            number if number.bytes().all(|b| b.is_ascii_digit()) => number
+                .parse()
+                .ok()
+                .and_then(|n: i32| Signal::try_from(n).ok()),
+            sig if sig.starts_with("SIG") => Signal::from_str(sig).ok(),
+            sig => Signal::from_str(&format!("SIG{}", sig)).ok(),
+        };
+        signal
+            .map(Condition::Signal)
+            .ok_or_else(|| format!("{}: invalid signal specification", name))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Exit => write!(f, "EXIT"),
+            Condition::Signal(sig) => write!(f, "{}", &sig.as_str()[3..]),
+            Condition::Err => write!(f, "ERR"),
+        }
+    }
+}
+
+// the pipe from the handler, created by the first trap of a signal
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+// the signals caught by `catch`, one bit for each number
+static CAUGHT: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn catch(sig: libc::c_int) {
+    let byte = sig as u8;
+    // the interrupted code may check errno
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(
+            WRITE_FD.load(Ordering::SeqCst),
+            (&byte as *const u8).cast(),
+            1,
+        );
+        *libc::__errno_location() = errno;
+    }
+}
+
+// a descriptor among those of the shell with the pipe end moved to it
+fn move_fd(fd: RawFd) -> nix::Result<RawFd> {
+    let moved = save_fd(fd).map_err(|_| Errno::last());
+    unistd::close(fd)?;
+    moved
+}
+
+fn open_pipe() -> nix::Result<()> {
+    if READ_FD.load(Ordering::SeqCst) >= 0 {
+        return Ok(());
+    }
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+    READ_FD.store(move_fd(read)?, Ordering::SeqCst);
+    WRITE_FD.store(move_fd(write)?, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Make the signal run its trap (a command), be ignored (no command) or
+/// behave as without a trap (None).
+pub fn set_signal(sig: Signal, command: Option<&str>) -> nix::Result<()> {
+    let handler = match command {
+        Some("") => SigHandler::SigIgn,
+        Some(_) => {
+            open_pipe()?;
+            SigHandler::Handler(catch)
+        }
+        None => {
+            cleanup::untrapped(sig)?;
+            CAUGHT.fetch_and(!(1 << sig as u64), Ordering::SeqCst);
+            return Ok(());
+        }
+    };
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { signal::sigaction(sig, &action)? };
+    if command == Some("") {
+        CAUGHT.fetch_and(!(1 << sig as u64), Ordering::SeqCst);
+    } else {
+        CAUGHT.fetch_or(1 << sig as u64, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// In a forked child: the caught signals behave as without traps again and
+/// the pipe of the parent is closed, ignored signals stay ignored.
+pub fn forget() {
+    let caught = CAUGHT.swap(0, Ordering::SeqCst);
+    for sig in Signal::iterator().filter(|&sig| caught & (1 << sig as u64) != 0) {
+        cleanup::untrapped(sig).ok();
+    }
+    for fd in [&READ_FD, &WRITE_FD] {
+        let fd = fd.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            unistd::close(fd).ok();
+        }
+    }
+}
+
+// the signals caught since the last call, in order
+fn pending() -> Vec<Signal> {
+    let fd = READ_FD.load(Ordering::SeqCst);
+    let mut signals = Vec::new();
+    if fd < 0 {
+        return signals;
+    }
+    let mut buf = [0u8; 64];
+    loop {
+        match unistd::read(fd, &mut buf) {
+            Ok(0) | Err(Errno::EAGAIN) => break,
+            Ok(len) => signals.extend(
+                buf[..len]
+                    .iter()
+                    .filter_map(|&byte| Signal::try_from(byte as i32).ok()),
+            ),
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+    }
+    signals
+}
+
+// run the command of the trap, the status is the one before it unless it exits
+fn run(state: &State, command: &str) {
+    let status = state.status.get();
+    let trapping = state.trapping.replace(true);
+    eval(state, command);
+    state.trapping.set(trapping);
+    if state.running.get() {
+        state.set_status(&status);
+    }
+}
+
+/// Run the traps of the signals caught since the last time.
+pub fn run_pending(state: &State) {
+    for sig in pending() {
+        let command = state.traps.borrow().get(&Condition::Signal(sig)).cloned();
+        if let Some(command) = command {
+            run(state, &command);
+        }
+    }
+}
+
+/// Run the ERR trap after a failed command, except in a trap.
+pub fn run_err(state: &State) {
+    if state.trapping.get() {
+        return;
+    }
+    let command = state.traps.borrow().get(&Condition::Err).cloned();
+    if let Some(command) = command {
+        run(state, &command);
+    }
+}
+
+/// Run the EXIT trap once before the shell exits; `exit` in it sets the status.
+pub fn run_exit(state: &State) {
+    let command = state.traps.borrow_mut().remove(&Condition::Exit);
+    if let Some(command) = command {
+        state.running.set(true);
+        run(state, &command);
+        state.terminate();
+    }
+}
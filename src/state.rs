@@ -1,8 +1,19 @@
+use nix::sys::stat;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd;
 use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use crate::builtins::Builtins;
+use crate::builtins::{Builtin, Builtins};
+use crate::cleanup;
+use crate::editor::Completer;
+use crate::history::{self, History};
+use crate::shell::options::{Options, ShellOption};
+use crate::shell::trap::Condition;
 
 // I guess I could use std::process::ExitStatus, but let's play
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -32,58 +43,294 @@ impl fmt::Display for Status {
     }
 }
 
+// the umask can only be read by setting it
+fn current_umask() -> u32 {
+    let mask = stat::umask(stat::Mode::empty());
+    stat::umask(mask);
+    mask.bits()
+}
+
+/// Command running in the background.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,
+    pub pid: unistd::Pid,
+}
+
+/// Path of a command remembered by the hash table, with the number of its uses.
+#[derive(Debug, Clone)]
+pub struct Hashed {
+    pub path: PathBuf,
+    pub hits: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct State<'a> {
-    pub builtins: Builtins<'a>,
-    pub name: RefCell<String>,
-    pub depth: u32,
-    pub debug: Cell<bool>,
-    pub interactive: bool,
-    pub running: Cell<bool>,
-    pub status: Cell<Status>,
-    pub lastpid: Cell<unistd::Pid>,
+    pub(crate) builtins: RefCell<Builtins<'a>>,
+    pub(crate) name: RefCell<String>,
+    pub(crate) depth: u32,
+    /// Options switched by `set`.
+    pub(crate) options: Cell<Options>,
+    pub(crate) interactive: bool,
+    pub(crate) running: Cell<bool>,
+    pub(crate) status: Cell<Status>,
+    pub(crate) lastpid: Cell<unistd::Pid>,
+    pub(crate) vars: RefCell<HashMap<String, String>>,
+    pub(crate) dirstack: RefCell<Vec<String>>,
+    pub(crate) umask: Cell<u32>,
+    pub(crate) cleanup: RefCell<Vec<PathBuf>>,
+    pub(crate) history: RefCell<History>,
+    pub(crate) jobs: RefCell<Vec<Job>>,
+    pub(crate) completers: RefCell<HashMap<String, Completer>>,
+    pub(crate) hash: RefCell<HashMap<String, Hashed>>,
+    /// Set by a builtin whose redirections stay after it, i.e. `exec`.
+    pub(crate) keep_redirects: Cell<bool>,
+    /// Commands of the traps set by `trap`.
+    pub(crate) traps: RefCell<BTreeMap<Condition, String>>,
+    /// A trap is running, failures in it do not run the ERR trap.
+    pub(crate) trapping: Cell<bool>,
 }
 
 impl<'a> State<'a> {
-    pub fn new(name: &str, interactive: bool) -> State {
-        State {
-            builtins: Builtins::new(),
+    pub fn new(name: &str, interactive: bool) -> State<'_> {
+        let state = State {
+            builtins: RefCell::new(Builtins::new()),
             name: RefCell::new(String::from(name)),
             depth: 0,
-            debug: Cell::new(false),
+            options: Cell::new(Options::default()),
             interactive,
             running: Cell::new(true),
             status: Cell::new(Status(0)),
             lastpid: Cell::new(unistd::Pid::from_raw(0)),
-        }
+            vars: RefCell::new(env::vars().collect()),
+            dirstack: RefCell::new(Vec::new()),
+            umask: Cell::new(current_umask()),
+            cleanup: RefCell::new(Vec::new()),
+            history: RefCell::new(History::new()),
+            jobs: RefCell::new(Vec::new()),
+            completers: RefCell::new(HashMap::new()),
+            hash: RefCell::new(HashMap::new()),
+            keep_redirects: Cell::new(false),
+            traps: RefCell::new(BTreeMap::new()),
+            trapping: Cell::new(false),
+        };
+        state.init_pwd();
+        state
     }
 
-    pub fn sub(&self) -> State {
+    pub(crate) fn sub(&self) -> State<'_> {
         State {
-            builtins: self.builtins.clone(),
+            builtins: RefCell::new(self.builtins.borrow().clone()),
             name: self.name.clone(), // RefCell::new(String::from(self.name.borrow())),
             depth: self.depth + 1,
-            debug: Cell::new(self.debug.get()),
+            options: self.options.clone(),
             interactive: self.interactive,
             running: Cell::new(true),
             status: Cell::new(Status(0)),
             lastpid: Cell::new(unistd::Pid::from_raw(0)),
+            vars: self.vars.clone(),
+            dirstack: self.dirstack.clone(),
+            umask: self.umask.clone(),
+            cleanup: RefCell::new(Vec::new()),
+            history: self.history.clone(),
+            jobs: RefCell::new(Vec::new()),
+            completers: self.completers.clone(),
+            hash: self.hash.clone(),
+            keep_redirects: Cell::new(false),
+            // only the ignored signals stay as they are
+            traps: RefCell::new(
+                self.traps
+                    .borrow()
+                    .iter()
+                    .filter(|(cond, command)| {
+                        matches!(cond, Condition::Signal(_)) && command.is_empty()
+                    })
+                    .map(|(&cond, command)| (cond, command.clone()))
+                    .collect(),
+            ),
+            trapping: Cell::new(false),
         }
     }
 
-    pub fn terminate(&self) {
+    // keep the inherited PWD if it names the current directory (possibly through symlinks)
+    fn init_pwd(&self) {
+        let same = |pwd: &str| match (stat::stat(pwd), stat::stat(".")) {
+            (Ok(st1), Ok(st2)) => {
+                pwd.starts_with('/') && (st1.st_dev, st1.st_ino) == (st2.st_dev, st2.st_ino)
+            }
+            _ => false,
+        };
+        match self.get_var("PWD") {
+            Some(pwd) if same(&pwd) => {}
+            _ => {
+                if let Ok(cwd) = unistd::getcwd() {
+                    self.set_var("PWD", &cwd.to_string_lossy());
+                }
+            }
+        }
+    }
+
+    /// Add the builtin, it replaces the builtin of the same name which is returned.
+    pub fn register_builtin(&self, builtin: Builtin<'a>) -> Option<Builtin<'a>> {
+        self.builtins.borrow_mut().register(builtin)
+    }
+
+    /// Remove the builtin of the name and return it.
+    pub fn unregister_builtin(&self, name: &str) -> Option<Builtin<'a>> {
+        self.builtins.borrow_mut().unregister(name)
+    }
+
+    /// Is there an enabled builtin of the name.
+    pub fn has_builtin(&self, name: &str) -> bool {
+        self.builtins.borrow().find(name).is_some()
+    }
+
+    /// The name of the shell, set by `name word`.
+    pub fn name(&self) -> String {
+        self.name.borrow().clone()
+    }
+
+    /// The status of the last command.
+    pub fn status(&self) -> Status {
+        self.status.get()
+    }
+
+    /// False after `exit`, the shell runs no more commands.
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    pub(crate) fn terminate(&self) {
         self.running.set(false);
     }
 
-    pub fn set_status_code(&self, code: i32) {
+    pub(crate) fn set_status_code(&self, code: i32) {
         self.status.set(Status(code));
     }
 
-    pub fn set_status(&self, status: &Status) {
+    pub(crate) fn set_status(&self, status: &Status) {
         self.set_status_code(status.0);
     }
 
+    pub fn option(&self, opt: ShellOption) -> bool {
+        self.options.get().has(opt)
+    }
+
+    pub fn set_option(&self, opt: ShellOption, on: bool) {
+        self.options.set(self.options.get().with(opt, on));
+    }
+
     pub fn set_name(&self, name: &str) {
         *self.name.borrow_mut() = String::from(name);
     }
+
+    /// Set the file mode creation mask of the shell process.
+    pub(crate) fn set_umask(&self, mask: u32) {
+        stat::umask(stat::Mode::from_bits_truncate(mask));
+        self.umask.set(mask);
+    }
+
+    /// Remove the path when this shell exits, or the whole process dies by a signal.
+    pub(crate) fn add_cleanup(&self, path: &Path) {
+        // the current directory may change before the removal
+        let path = match unistd::getcwd() {
+            Ok(cwd) => cwd.join(path),
+            Err(_) => path.to_path_buf(),
+        };
+        cleanup::register(&path);
+        self.cleanup.borrow_mut().push(path);
+    }
+
+    /// Remove the paths added by `add_cleanup`, the newest first.
+    pub fn run_cleanup(&self) {
+        for path in self.cleanup.borrow_mut().drain(..).rev() {
+            cleanup::remove(&path);
+        }
+    }
+
+    /// Remember the command started in the background.
+    pub(crate) fn add_job(&self, pid: unistd::Pid) {
+        self.lastpid.set(pid);
+        let mut jobs = self.jobs.borrow_mut();
+        let id = jobs.last().map_or(1, |job| job.id + 1);
+        jobs.push(Job { id, pid });
+    }
+
+    /// Forget the background commands which have finished.
+    pub(crate) fn reap_jobs(&self) {
+        self.jobs.borrow_mut().retain(|job| {
+            matches!(
+                waitpid(job.pid, Some(WaitPidFlag::WNOHANG)),
+                Ok(WaitStatus::StillAlive)
+            )
+        });
+    }
+
+    fn history_size(&self) -> usize {
+        self.get_var("HISTSIZE")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(history::DEFAULT_SIZE)
+    }
+
+    /// Load the history from HISTFILE, ~/.frish_history by default.
+    pub fn load_history(&self) -> io::Result<()> {
+        let file = match (self.get_var("HISTFILE"), self.get_var("HOME")) {
+            (Some(file), _) => PathBuf::from(file),
+            (None, Some(home)) => Path::new(&home).join(".frish_history"),
+            (None, None) => return Ok(()),
+        };
+        let size = self.history_size();
+        self.history.borrow_mut().load(&file, size)
+    }
+
+    /// Add the command to the history as allowed by HISTSIZE and HISTCONTROL.
+    pub(crate) fn add_history(&self, line: &str) -> io::Result<()> {
+        let control = history::Control::parse(&self.get_var("HISTCONTROL").unwrap_or_default());
+        let size = self.history_size();
+        self.history.borrow_mut().add(line, size, control)
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        self.vars.borrow().get(name).cloned()
+    }
+
+    /// Set the variable, it is exported to the environment of the commands too.
+    /// A new PATH empties the hash table of the commands.
+    pub fn set_var(&self, name: &str, value: &str) {
+        if name == "PATH" {
+            self.hash.borrow_mut().clear();
+        }
+        env::set_var(name, value);
+        self.vars
+            .borrow_mut()
+            .insert(String::from(name), String::from(value));
+    }
+
+    /// Make the environment and the current directory of the process those
+    /// of this shell again, after a subshell in the same process.
+    pub(crate) fn restore_environment(&self) {
+        let vars = self.vars.borrow();
+        for (name, _) in env::vars() {
+            if !vars.contains_key(&name) {
+                env::remove_var(name);
+            }
+        }
+        for (name, value) in vars.iter() {
+            env::set_var(name, value);
+        }
+        if let Some(pwd) = vars.get("PWD") {
+            env::set_current_dir(pwd).ok();
+        }
+    }
+
+    /// The directory with `$HOME` replaced by `~`.
+    pub(crate) fn tilde_dir(&self, dir: &str) -> String {
+        match self.get_var("HOME").filter(|home| home.len() > 1) {
+            Some(home) if dir == home => String::from("~"),
+            Some(home) if dir.starts_with(&format!("{}/", home)) => {
+                format!("~{}", &dir[home.len()..])
+            }
+            _ => String::from(dir),
+        }
+    }
 }
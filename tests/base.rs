@@ -0,0 +1,275 @@
+mod common;
+
+use common::{run, run_args, run_c, TempDir};
+use std::process::Command;
+
+// every builtin, each one is exercised by some test
+const BUILTINS: &[&str] = &[
+    "help",
+    "enable",
+    "disable",
+    "name",
+    "loglevel",
+    "set",
+    "history",
+    "complete",
+    "print",
+    "echo",
+    "dir.change",
+    "dir.where",
+    "dir.push",
+    "dir.pop",
+    "dir.stack",
+    "dir.make",
+    "dir.remove",
+    "dir.list",
+    "dir.inspect",
+    "dir.tree",
+    "dir.copy",
+    "dir.size",
+    "dir.find",
+    "dir.temp",
+    "link.hard",
+    "link.soft",
+    "link.read",
+    "unlink",
+    "rename",
+    "cpcat",
+    "file.head",
+    "file.tail",
+    "file.count",
+    "file.hash",
+    "file.temp",
+    "file.stat",
+    "file.chmod",
+    "file.chown",
+    "file.touch",
+    "file.truncate",
+    "umask",
+    "pid",
+    "ppid",
+    "lastpid",
+    "status",
+    "exit",
+    "depth",
+    "subshell",
+    "pipes",
+    "type",
+    "which",
+    "command",
+    "builtin",
+    "exec",
+    "hash",
+    "trap",
+];
+
+#[test]
+fn help_lists_all_builtins() {
+    let out = run("help\n");
+    let names: Vec<_> = out
+        .stdout
+        .lines()
+        .map(|line| line.split_whitespace().next().unwrap_or(""))
+        .collect();
+    assert_eq!(names, BUILTINS);
+    assert_eq!(out.code, 0);
+}
+
+#[test]
+fn builtin_help_option() {
+    let out = run("file.head --help\n");
+    assert!(out.stdout.contains("-n"), "{}", out.stdout);
+    assert_eq!(out.code, 0);
+    let out = run("file.head --bogus\n");
+    assert!(!out.stderr.is_empty());
+    assert_eq!(out.code, 2);
+}
+
+#[test]
+fn name() {
+    let out = run("name\nname other\nname\n");
+    assert_eq!(out.stdout, "frish\nother\n");
+}
+
+#[test]
+fn loglevel() {
+    let out = run("loglevel\nloglevel Info\nloglevel Error\nloglevel\n");
+    assert_eq!(
+        out.stdout,
+        "Log level is ERROR\nLog level is INFO\nLog level is ERROR\nLog level is ERROR\n"
+    );
+}
+
+#[test]
+fn print_and_echo() {
+    let out = run("print a  b\nprint \"c d\"\necho\necho x y\n");
+    assert_eq!(out.stdout, "a bc d\nx y\n");
+}
+
+#[test]
+fn echo_options() {
+    let out = run("echo -n a\necho -e \"b\\tc\\x41\\0101\"\necho -e \"d\\ce\"\necho -- -n\n");
+    assert_eq!(out.stdout, "ab\tcAA\nd-- -n\n");
+    // only words made of the options n and e are options
+    let out = run("echo -foo bar\necho --help\necho -ne \"x\\ty\" -n\necho -\n");
+    assert_eq!(out.stdout, "-foo bar\n--help\nx\ty -n-\n");
+    assert_eq!(out.code, 0);
+    // numeric escapes are bytes, here of a UTF-8 character
+    let out = run("echo -e \"\\xe2\\x82\\xac \\0342\\0202\\0254\"\n");
+    assert_eq!(out.stdout, "\u{20ac} \u{20ac}\n");
+}
+
+#[test]
+fn history_is_empty_without_terminal() {
+    let out = run("echo a\nhistory\n");
+    assert_eq!(out.stdout, "a\n");
+    let out = run("history -d 5\n");
+    assert!(
+        out.stderr.contains("No such history entry"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn complete_registers_completers() {
+    let out = run(
+        "complete -W \"start stop\" svc ctl\ncomplete -C \"echo x\" foo\ncomplete\n\
+         complete -r ctl foo\ncomplete\n",
+    );
+    assert_eq!(
+        out.stdout,
+        "complete -W 'start stop' ctl\ncomplete -C 'echo x' foo\n\
+         complete -W 'start stop' svc\ncomplete -W 'start stop' svc\n"
+    );
+    // the printed commands read back
+    let out = run("complete -C 'echo \"x\"' foo\ncomplete -W \"it's\" bar\ncomplete\n");
+    assert_eq!(
+        out.stdout,
+        "complete -W \"it's\" bar\ncomplete -C 'echo \"x\"' foo\n"
+    );
+    assert_eq!(run(&format!("{}complete\n", out.stdout)).stdout, out.stdout);
+    let out = run("complete -W a -C b x\n");
+    assert_eq!(out.code, 2);
+}
+
+#[test]
+fn enable_and_disable() {
+    // the disabled echo falls through to the external program
+    let out = run("disable echo\necho -E x\nenable -n\nenable echo\necho -E y\n");
+    assert_eq!(out.stdout, "x\nenable -n echo\n-E y\n");
+    let out = run("enable -d echo\necho -n z\nenable -a\n");
+    assert!(out.stdout.starts_with("zenable help\nenable enable\n"));
+    assert!(!out.stdout.contains("enable echo\n"), "{}", out.stdout);
+    let out = run("disable nosuch\n");
+    assert!(
+        out.stderr.contains("nosuch: No such builtin"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn plugin_builtins() {
+    let dir = TempDir::new();
+    let plugin = dir.join("hello.so");
+    let source = concat!(env!("CARGO_MANIFEST_DIR"), "/plugin/hello.c");
+    let compiled = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(&plugin)
+        .arg(source)
+        .status();
+    if !matches!(compiled, Ok(status) if status.success()) {
+        eprintln!("no C compiler, the plugin is not tested");
+        return;
+    }
+    let script = format!(
+        "enable -f {0} fail\nhello\nfail a b\nstatus\nenable -f {0}\nhello a b\n",
+        plugin.display()
+    );
+    let out = run(&script);
+    assert_eq!(out.stdout, "3\nHello a b!\n");
+    let out = run(&format!("enable -f {} nosuch\n", plugin.display()));
+    assert!(
+        out.stderr.contains("nosuch: No such builtin"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(out.code, 1);
+    let out = run(&format!("enable -f {}\n", dir.join("missing.so").display()));
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn command_option() {
+    let out = run_c("echo one\necho \"two\nlines\"\necho three");
+    assert_eq!(out.stdout, "one\ntwo\nlines\nthree\n");
+    assert_eq!(out.code, 0);
+}
+
+#[test]
+fn command_option_status() {
+    let out = run_c("exit 7\necho not reached");
+    assert_eq!(out.stdout, "");
+    assert_eq!(out.code, 7);
+    let out = run_c("file.stat /nonexistent");
+    assert_eq!(out.code, 2);
+}
+
+#[test]
+fn invalid_arguments() {
+    let out = run_c("");
+    assert_eq!(out.code, 0);
+    let out = common::frish().arg("-q").output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    let out = common::frish().arg("-c").output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    let out = common::frish().args(["-o", "nosuch"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn set_options() {
+    let out = run("set -e -o pipefail\nset +e -xC\nset +o\nset -q\nset -eo\nset word\n");
+    assert_eq!(
+        out.stdout,
+        "set +o errexit\nset -o noclobber\nset +o noexec\nset +o nounset\n\
+         set -o pipefail\nset -o xtrace\n"
+    );
+    assert!(
+        out.stderr.contains("set: -q: invalid option"),
+        "{}",
+        out.stderr
+    );
+    assert!(out.stderr.contains("set: -eo: option requires an argument"));
+    assert!(out.stderr.contains("set: extra operand 'word'"));
+    let out = run_args(&["-u", "-c", "set -o"], "");
+    assert!(out
+        .stdout
+        .starts_with("errexit        off\nnoclobber      off\n"));
+    assert!(out.stdout.contains("nounset        on\n"));
+    // accepted without effect until variables are expanded
+    let out = run("set -eu\necho ok\n");
+    assert_eq!((out.stdout.as_str(), out.code), ("ok\n", 0));
+}
+
+#[test]
+fn quoted_words() {
+    let out = run("echo 'ab'c \"x\"y z\necho 'a;'b \"(c)\"d\n");
+    assert_eq!(out.stdout, "ab c x y z\na; b (c) d\n");
+}
+
+#[test]
+fn continuation_lines() {
+    let out = run("echo a \\\nb\necho 'c\nd'\n");
+    assert_eq!(out.stdout, "a b\nc\nd\n");
+}
+
+#[test]
+fn empty_lines_and_only_redirections() {
+    let out = run("\n   \n&\n>x\necho ok\n");
+    assert_eq!(out.stdout, "ok\n");
+    assert_eq!(out.code, 0);
+}
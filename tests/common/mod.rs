@@ -0,0 +1,253 @@
+// Helpers running the frish binary with scripted input, shared by the test files.
+#![allow(dead_code)]
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{openpty, Winsize};
+use nix::unistd;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Result of running the shell.
+#[derive(Debug)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
+/// Command running the shell binary of the crate with a clean environment.
+pub fn frish() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_frish"));
+    command
+        .env_clear()
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .env("HOME", "/nonexistent")
+        .env("TERM", "xterm");
+    command
+}
+
+fn output(command: &mut Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot run frish");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    Output {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code().unwrap_or(-1),
+    }
+}
+
+/// Run the script given on the standard input.
+pub fn run(script: &str) -> Output {
+    output(&mut frish(), script)
+}
+
+/// Run the script given on the standard input in the directory.
+pub fn run_in(dir: &Path, script: &str) -> Output {
+    output(frish().current_dir(dir).env("PWD", dir), script)
+}
+
+/// Run the script given on the standard input with more environment variables.
+pub fn run_env(vars: &[(&str, &str)], script: &str) -> Output {
+    output(frish().envs(vars.iter().copied()), script)
+}
+
+/// Run the script given on the standard input, with the arguments of the shell.
+pub fn run_args(args: &[&str], script: &str) -> Output {
+    output(frish().args(args), script)
+}
+
+/// Run the command given by `-c`.
+pub fn run_c(command: &str) -> Output {
+    output(frish().arg("-c").arg(command), "")
+}
+
+/// Directory for the files of a test, removed on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "frish-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).unwrap();
+        // resolve symbolic links so that the paths match PWD
+        let path = fs::canonicalize(path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Create a file with the content.
+    pub fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.join(name)).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// Content of the file written by a background command, waits until it appears.
+pub fn wait_for_file(path: &Path) -> String {
+    let start = Instant::now();
+    loop {
+        match fs::read_to_string(path) {
+            Ok(text) if text.ends_with('\n') => return text,
+            _ if start.elapsed() > TIMEOUT => panic!("{} not written", path.display()),
+            _ => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+/// The text without ANSI escape sequences and carriage returns.
+pub fn strip_escapes(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    chars.by_ref().find(|ch| ('@'..='~').contains(ch));
+                }
+            }
+            '\r' => {}
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interactive shell on a pseudo-terminal.
+pub struct Pty {
+    master: File,
+    child: Child,
+    /// Everything read from the terminal.
+    pub output: String,
+}
+
+impl Pty {
+    pub fn spawn(envs: &[(&str, &str)]) -> Pty {
+        let size = Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = openpty(&size, None).expect("cannot open a pseudo-terminal");
+        let slave = unsafe { File::from_raw_fd(pty.slave) };
+        let mut command = frish();
+        command
+            .envs(envs.iter().copied())
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave.try_clone().unwrap())
+            .stderr(slave);
+        // a session of its own with the terminal
+        unsafe {
+            command.pre_exec(|| {
+                unistd::setsid().ok();
+                Ok(())
+            });
+        }
+        let child = command.spawn().expect("cannot run frish");
+        Pty {
+            master: unsafe { File::from_raw_fd(pty.master) },
+            child,
+            output: String::new(),
+        }
+    }
+
+    /// Type the keys.
+    pub fn send(&mut self, keys: &str) {
+        self.master.write_all(keys.as_bytes()).unwrap();
+    }
+
+    // read what is available within the time
+    fn read(&mut self, timeout: Duration) -> bool {
+        let mut fds = [PollFd::new(self.master.as_raw_fd(), PollFlags::POLLIN)];
+        if !matches!(poll(&mut fds, timeout.as_millis() as i32), Ok(n) if n > 0) {
+            return false;
+        }
+        let mut buf = [0; 4096];
+        match self.master.read(&mut buf) {
+            Ok(n) if n > 0 => {
+                self.output.push_str(&String::from_utf8_lossy(&buf[..n]));
+                true
+            }
+            // EIO after the shell exits
+            _ => false,
+        }
+    }
+
+    /// Wait until the visible output contains the text, returns the output
+    /// up to its end and forgets it.
+    pub fn expect(&mut self, text: &str) -> String {
+        let start = Instant::now();
+        loop {
+            let visible = strip_escapes(&self.output);
+            if let Some(i) = visible.find(text) {
+                // keep the raw output after the text
+                let mut seen = String::new();
+                let mut rest = self.output.as_str();
+                while strip_escapes(&seen).len() < i + text.len() && !rest.is_empty() {
+                    let ch = rest.chars().next().unwrap();
+                    seen.push(ch);
+                    rest = &rest[ch.len_utf8()..];
+                }
+                let rest = rest.to_string();
+                self.output = rest;
+                return seen;
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= TIMEOUT {
+                panic!("timeout waiting for {:?}, output: {:?}", text, self.output);
+            }
+            self.read(TIMEOUT - elapsed);
+        }
+    }
+
+    /// Wait for the shell to exit and return its status.
+    pub fn wait(mut self) -> i32 {
+        // drain the output so that the shell is not blocked
+        while self.read(Duration::from_millis(100)) {}
+        self.child.wait().unwrap().code().unwrap_or(-1)
+    }
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::{run_in, TempDir};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+#[test]
+fn change_and_where() {
+    let dir = TempDir::new();
+    fs::create_dir(dir.join("sub")).unwrap();
+    let out = run_in(
+        dir.path(),
+        "dir.where\ndir.change sub\ndir.where\ndir.change -\ndir.change nonexistent\n",
+    );
+    let path = dir.path().display();
+    assert_eq!(out.stdout, format!("{0}\n{0}/sub\n{0}\n", path));
+    assert!(out.stderr.contains("No such file"), "{}", out.stderr);
+}
+
+#[test]
+fn change_home_and_pwd() {
+    let dir = TempDir::new();
+    let out = common::frish()
+        .current_dir("/")
+        .env("HOME", dir.path())
+        .arg("-c")
+        .arg("dir.change\ndir.where\nexit")
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        format!("{}\n", dir.path().display())
+    );
+}
+
+#[test]
+fn push_pop_stack() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    let out = run_in(
+        dir.path(),
+        "dir.push a\ndir.push b\ndir.stack -v\ndir.pop\ndir.where\ndir.stack -c\ndir.stack\n",
+    );
+    let path = dir.path().display();
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!("{0}/a {0}", path),
+            format!("{0}/a/b {0}/a {0}", path),
+            format!(" 0  {}/a/b", path),
+            format!(" 1  {}/a", path),
+            format!(" 2  {}", path),
+            format!("{0}/a {0}", path),
+            format!("{}/a", path),
+            format!("{}/a", path),
+        ]
+    );
+}
+
+#[test]
+fn make_and_remove() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "dir.make x\ndir.make -p y/z\ndir.make x\ndir.remove x\ndir.remove y\ndir.remove -r y\n",
+    );
+    assert!(!dir.join("x").exists());
+    assert!(!dir.join("y").exists());
+    // the existing x and the non-empty y
+    assert_eq!(out.stderr.lines().count(), 2, "{}", out.stderr);
+}
+
+#[test]
+fn make_respects_umask() {
+    let dir = TempDir::new();
+    run_in(dir.path(), "umask 077\ndir.make private\n");
+    let mode = fs::metadata(dir.join("private"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o700);
+}
+
+#[test]
+fn list() {
+    let dir = TempDir::new();
+    dir.write("b.txt", "12345");
+    dir.write("a.txt", "1");
+    dir.write(".hidden", "");
+    let out = run_in(dir.path(), "dir.list\ndir.list -S\ndir.list -a -r\n");
+    assert_eq!(
+        out.stdout,
+        "a.txt\nb.txt\nb.txt\na.txt\nb.txt\na.txt\n.hidden\n..\n.\n"
+    );
+}
+
+#[test]
+fn list_long_and_inspect() {
+    let dir = TempDir::new();
+    dir.write("file", "abc");
+    let out = run_in(dir.path(), "dir.list -l\ndir.inspect\n");
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", out.stdout);
+    for line in lines {
+        assert!(line.starts_with("-rw"), "{}", line);
+        assert!(line.ends_with(" file"), "{}", line);
+        assert!(line.contains(" 3 "), "{}", line);
+    }
+}
+
+#[test]
+fn tree() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("t/a/b")).unwrap();
+    fs::write(dir.join("t/a/f"), "").unwrap();
+    let out = run_in(dir.path(), "dir.tree t\ndir.tree -L 1 t\n");
+    assert_eq!(
+        out.stdout,
+        "t\n└── a\n    ├── b\n    └── f\n\n2 directories, 1 file\n\
+         t\n└── a\n\n1 directory, 0 files\n"
+    );
+}
+
+#[test]
+fn copy() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("src/sub")).unwrap();
+    fs::write(dir.join("src/f"), "data").unwrap();
+    fs::write(dir.join("src/sub/g"), "more").unwrap();
+    run_in(dir.path(), "dir.copy src flat\ndir.copy -r src deep\n");
+    assert_eq!(dir.read("flat/f"), "data");
+    assert!(!dir.join("flat/sub").exists());
+    assert_eq!(dir.read("deep/sub/g"), "more");
+}
+
+#[test]
+fn copy_into_itself() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("d/sub")).unwrap();
+    let out = run_in(
+        dir.path(),
+        "dir.copy -r d d/sub/copy
+dir.copy -r d d/sub
+dir.copy -r d d
+",
+    );
+    assert_eq!(out.code, 1);
+    assert_eq!(
+        out.stderr.matches("into itself").count(),
+        3,
+        "{}",
+        out.stderr
+    );
+    assert_eq!(fs::read_dir(dir.join("d/sub")).unwrap().count(), 0);
+    run_in(
+        dir.path(),
+        "dir.copy -r d e
+",
+    );
+    assert!(dir.join("e/sub").is_dir());
+}
+
+#[test]
+fn size() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("d/e")).unwrap();
+    fs::write(dir.join("d/e/f"), vec![b'x'; 10000]).unwrap();
+    let out = run_in(dir.path(), "dir.size d\ndir.size -s d\n");
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", out.stdout);
+    assert!(lines[0].ends_with("\td/e"));
+    assert!(lines[1].ends_with("\td"));
+    assert!(lines[2].ends_with("\td"));
+    let kilobytes: u64 = lines[2].split('\t').next().unwrap().parse().unwrap();
+    assert!(kilobytes >= 10, "{}", kilobytes);
+}
+
+#[test]
+fn find() {
+    let dir = TempDir::new();
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::write(dir.join("a/x.txt"), "").unwrap();
+    fs::write(dir.join("a/b/y.txt"), "12345").unwrap();
+    fs::write(dir.join("a/b/z.rs"), "").unwrap();
+    let out = run_in(
+        dir.path(),
+        "dir.find a -name *.txt\ndir.find a -type d\ndir.find a -type f -size +1c\n",
+    );
+    let mut lines: Vec<_> = out.stdout.lines().collect();
+    lines[..2].sort();
+    lines[2..4].sort();
+    assert_eq!(lines, ["a/b/y.txt", "a/x.txt", "a", "a/b", "a/b/y.txt"]);
+}
+
+#[test]
+fn find_exec() {
+    let dir = TempDir::new();
+    fs::create_dir(dir.join("a")).unwrap();
+    fs::write(dir.join("a/f"), "content\n").unwrap();
+    let out = run_in(
+        dir.path(),
+        "dir.find a -type f -exec file.head {} \";\" -name g\n",
+    );
+    assert_eq!(out.stdout, "content\n");
+    let out = run_in(dir.path(), "dir.find a -exec echo {} ';' -type f -print0\n");
+    assert_eq!(out.stdout, "a\na/f\na/f\0");
+}
+
+#[test]
+fn find_exec_special_names() {
+    let dir = TempDir::new();
+    fs::create_dir(dir.join("b")).unwrap();
+    for name in ["a;b", "q'x\"y", "c>d"] {
+        fs::write(dir.join("b").join(name), "").unwrap();
+    }
+    let out = run_in(dir.path(), "dir.find b -type f -exec echo {} \";\"\n");
+    let mut lines: Vec<_> = out.stdout.lines().collect();
+    lines.sort();
+    assert_eq!(lines, ["b/a;b", "b/c>d", "b/q'x\"y"]);
+    assert_eq!(out.stderr, "");
+    assert!(!dir.join("d").exists());
+}
+
+#[test]
+fn temp() {
+    let dir = TempDir::new();
+    let script = format!(
+        "dir.temp -p {0}\ndir.temp {0}/mineXXXXXX\ndir.temp --cleanup -p {0}\n",
+        dir.path().display()
+    );
+    let out = run_in(dir.path(), &script);
+    let paths: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(paths.len(), 3, "{}", out.stdout);
+    assert!(Path::new(paths[0]).is_dir());
+    assert!(paths[1].contains("/mine"));
+    assert!(Path::new(paths[1]).is_dir());
+    // removed at the exit
+    assert!(!Path::new(paths[2]).exists());
+}
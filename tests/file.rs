@@ -0,0 +1,232 @@
+mod common;
+
+use common::{run_in, TempDir};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{self, Pid};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Stdio;
+
+const LINES: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+
+#[test]
+fn links() {
+    let dir = TempDir::new();
+    dir.write("f", "data");
+    let out = run_in(
+        dir.path(),
+        "link.hard f h\nlink.soft f s\nlink.read s\nlink.soft g s\nlink.soft -f g s\nlink.read s\n",
+    );
+    assert_eq!(out.stdout, "f\ng\n");
+    assert_eq!(out.stderr.lines().count(), 1, "{}", out.stderr);
+    assert_eq!(fs::metadata(dir.join("f")).unwrap().nlink(), 2);
+}
+
+#[test]
+fn unlink_and_rename() {
+    let dir = TempDir::new();
+    dir.write("a", "1");
+    dir.write("b", "2");
+    dir.write("c", "3");
+    let out = run_in(
+        dir.path(),
+        "rename a d\nrename -n b c\nunlink b\nunlink b\nunlink -f b\n",
+    );
+    assert_eq!(dir.read("d"), "1");
+    assert_eq!(dir.read("c"), "3");
+    assert!(!dir.join("b").exists());
+    // the existing c and the missing b
+    assert_eq!(out.stderr.lines().count(), 2, "{}", out.stderr);
+}
+
+#[test]
+fn cpcat() {
+    let dir = TempDir::new();
+    dir.write("src", "text\n");
+    let out = run_in(
+        dir.path(),
+        "cpcat src dst\ncpcat -a src dst\ncpcat -n src dst\ncpcat dst -\n",
+    );
+    assert_eq!(out.stdout, "text\ntext\n");
+    assert_eq!(out.stderr.lines().count(), 1, "{}", out.stderr);
+}
+
+#[test]
+fn cpcat_redirected() {
+    let dir = TempDir::new();
+    dir.write("in", "input\n");
+    run_in(dir.path(), "cpcat - copy <in\ncpcat in - >out\n");
+    assert_eq!(dir.read("copy"), "input\n");
+    assert_eq!(dir.read("out"), "input\n");
+}
+
+#[test]
+fn head() {
+    let dir = TempDir::new();
+    dir.write("f", LINES);
+    dir.write("g", "x\ny\n");
+    let out = run_in(
+        dir.path(),
+        "file.head f\nfile.head -n 2 f g\nfile.head -c 3 f\n",
+    );
+    assert_eq!(
+        out.stdout,
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n==> f <==\n1\n2\n\n==> g <==\nx\ny\n1\n2"
+    );
+}
+
+#[test]
+fn tail() {
+    let dir = TempDir::new();
+    dir.write("f", LINES);
+    let out = run_in(
+        dir.path(),
+        "file.tail -n 3 f\nfile.tail -c 3 f\nfile.tail f\n",
+    );
+    assert_eq!(
+        out.stdout,
+        "10\n11\n12\n12\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n"
+    );
+}
+
+#[test]
+fn tail_follow_stdin() {
+    let dir = TempDir::new();
+    dir.write("f", "a\nb\n");
+    let mut child = common::frish()
+        .args(["-c", "file.tail -f -n1"])
+        .stdin(File::open(dir.join("f")).unwrap())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0; 64];
+    let len = stdout.read(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"b\n");
+    // the watch is set after the tail is printed
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let mut file = OpenOptions::new().append(true).open(dir.join("f")).unwrap();
+    file.write_all(b"c\n").unwrap();
+    let len = stdout.read(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"c\n");
+    signal::kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "");
+    assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn count() {
+    let dir = TempDir::new();
+    dir.write("f", "one two\nthree\n");
+    dir.write("g", "x");
+    let out = run_in(dir.path(), "file.count f\nfile.count -l f g\n");
+    assert_eq!(out.stdout, " 2  3 14 f\n 2 f\n 0 g\n 2 total\n");
+}
+
+#[test]
+fn hash() {
+    let dir = TempDir::new();
+    dir.write("f", "abc");
+    let out = run_in(
+        dir.path(),
+        "file.hash f\nfile.hash -a md5 f\nfile.hash -a sha1 f\n",
+    );
+    assert_eq!(
+        out.stdout,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  f\n\
+         900150983cd24fb0d6963f7d28e17f72  f\n"
+    );
+    assert_eq!(out.code, 2);
+}
+
+#[test]
+fn temp() {
+    let dir = TempDir::new();
+    let script = format!(
+        "file.temp -p {0}\nfile.temp --cleanup -p {0}\n",
+        dir.path().display()
+    );
+    let out = run_in(dir.path(), &script);
+    let paths: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(paths.len(), 2, "{}", out.stdout);
+    assert!(Path::new(paths[0]).is_file());
+    assert!(paths[0].contains("/frish."));
+    assert!(!Path::new(paths[1]).exists());
+}
+
+#[test]
+fn stat() {
+    let dir = TempDir::new();
+    dir.write("f", "12345");
+    let out = run_in(dir.path(), "file.stat -c \"%n %s\" f\nfile.stat f\n");
+    let mut lines = out.stdout.lines();
+    assert_eq!(lines.next(), Some("f 5"));
+    assert!(out.stdout.contains("Size: 5"), "{}", out.stdout);
+}
+
+#[test]
+fn chmod() {
+    let dir = TempDir::new();
+    dir.write("f", "");
+    let mode = |dir: &TempDir| fs::metadata(dir.join("f")).unwrap().permissions().mode() & 0o7777;
+    run_in(dir.path(), "file.chmod 640 f\n");
+    assert_eq!(mode(&dir), 0o640);
+    run_in(dir.path(), "file.chmod u+x,g-r,o=w f\n");
+    assert_eq!(mode(&dir), 0o702);
+    let out = run_in(dir.path(), "file.chmod 999 f\n");
+    assert_ne!(out.code, 0);
+}
+
+#[test]
+fn chown() {
+    let dir = TempDir::new();
+    dir.write("f", "");
+    let (uid, gid) = (unistd::getuid(), unistd::getgid());
+    let out = run_in(dir.path(), &format!("file.chown {}:{} f\n", uid, gid));
+    assert_eq!(out.stderr, "");
+    let meta = fs::metadata(dir.join("f")).unwrap();
+    assert_eq!((meta.uid(), meta.gid()), (uid.as_raw(), gid.as_raw()));
+}
+
+#[test]
+fn touch() {
+    let dir = TempDir::new();
+    run_in(
+        dir.path(),
+        "file.touch new\nfile.touch -c missing\nfile.touch -t 200001011200 new\n",
+    );
+    assert!(!dir.join("missing").exists());
+    let meta = fs::metadata(dir.join("new")).unwrap();
+    assert_eq!(meta.len(), 0);
+    // noon on 2000-01-01 in any time zone
+    assert!((946_728_000 - 14 * 3600..=946_728_000 + 14 * 3600).contains(&meta.mtime()));
+}
+
+#[test]
+fn truncate() {
+    let dir = TempDir::new();
+    dir.write("f", "1234567890");
+    let size = |dir: &TempDir| fs::metadata(dir.join("f")).unwrap().len();
+    run_in(dir.path(), "file.truncate 4 f\n");
+    assert_eq!(dir.read("f"), "1234");
+    run_in(dir.path(), "file.truncate +2K f\n");
+    assert_eq!(size(&dir), 2052);
+    run_in(dir.path(), "file.truncate -- -52 f\n");
+    assert_eq!(size(&dir), 2000);
+}
+
+#[test]
+fn umask() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "umask 027\numask\numask -S\numask u=rwx,g=,o=\numask\ncpcat - f\n",
+    );
+    assert_eq!(out.stdout, "0027\nu=rwx,g=rx,o=\n0077\n");
+    let mode = fs::metadata(dir.join("f")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::{strip_escapes, Pty, TempDir};
+
+// a shell with its history in the directory
+fn spawn(dir: &TempDir, envs: &[(&str, &str)]) -> Pty {
+    let histfile = dir.join("history");
+    let mut all = vec![("HISTFILE", histfile.to_str().unwrap())];
+    all.extend_from_slice(envs);
+    Pty::spawn(&all)
+}
+
+#[test]
+fn prompt_and_exit() {
+    let dir = TempDir::new();
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    pty.send("echo hello\r");
+    pty.expect("hello\n");
+    pty.expect("frish> ");
+    pty.send("name sh\r");
+    pty.expect("sh> ");
+    pty.send("exit 3\r");
+    assert_eq!(pty.wait(), 3);
+}
+
+#[test]
+fn end_of_input() {
+    let dir = TempDir::new();
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    pty.send("\x04");
+    assert_eq!(pty.wait(), 0);
+}
+
+#[test]
+fn prompt_escapes() {
+    let dir = TempDir::new();
+    let mut pty = spawn(
+        &dir,
+        &[
+            ("PS1", "[\\s|\\L|\\?|\\j|\\D{%%}|\\q]\\$ "),
+            ("PS2", "more> "),
+        ],
+    );
+    pty.expect("[frish|0|0|0|%|\\q]");
+    pty.send("file.stat /nonexistent\r");
+    pty.expect("[frish|0|2|0|%|\\q]");
+    pty.send("echo \"a\r");
+    pty.expect("more> ");
+    pty.send("b\"\r");
+    pty.expect("a\nb\n");
+    pty.send("exit\r");
+    assert_eq!(pty.wait(), 0);
+}
+
+#[test]
+fn line_editing() {
+    let dir = TempDir::new();
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    // move to the start, kill the word and yank it at the end
+    pty.send("cho hi\x01e\x05 there\x17\x17\x19 X\r");
+    pty.expect("hi there X\n");
+    pty.send("bogus line\x03");
+    pty.expect("^C");
+    pty.send("exit\r");
+    assert_eq!(pty.wait(), 0);
+}
+
+#[test]
+fn history() {
+    let dir = TempDir::new();
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    pty.send("echo first\r");
+    pty.expect("first\n");
+    pty.send("echo second\r");
+    pty.expect("second\n");
+    // up twice recalls the first command
+    pty.send("\x1b[A\x1b[A\r");
+    pty.expect("first\n");
+    pty.send("!!\r");
+    pty.expect("echo first\n");
+    pty.expect("first\n");
+    pty.send("history\r");
+    let out = strip_escapes(&pty.expect("5  history"));
+    assert!(
+        out.contains("    1  echo first\n    2  echo second\n"),
+        "{}",
+        out
+    );
+    pty.send("exit\r");
+    pty.wait();
+    let saved = dir.read("history");
+    assert!(saved.starts_with("echo first\necho second\n"), "{}", saved);
+}
+
+#[test]
+fn history_search() {
+    let dir = TempDir::new();
+    dir.write("history", "echo apple\necho banana\n");
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    pty.send("\x12app\r");
+    pty.expect("apple\n");
+    pty.send("exit\r");
+    pty.wait();
+}
+
+#[test]
+fn completion() {
+    let dir = TempDir::new();
+    dir.write("unique-name.txt", "content\n");
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    let cd = format!("dir.change {}\r", dir.path().display());
+    pty.send(&cd);
+    pty.expect("frish> ");
+    pty.send("file.he\tuniq\t\r");
+    pty.expect("content\n");
+    // several candidates are listed
+    pty.send("dir.\t");
+    pty.expect("dir.change");
+    pty.expect("dir.where");
+    pty.send("\x15complete -W \"alpha beta\" foo\r");
+    pty.expect("frish> ");
+    pty.send("foo al\t");
+    pty.expect("foo alpha ");
+    pty.send("\x15exit\r");
+    assert_eq!(pty.wait(), 0);
+}
+
+#[test]
+fn highlighting() {
+    let dir = TempDir::new();
+    let mut pty = spawn(&dir, &[]);
+    pty.expect("frish> ");
+    pty.send("echo");
+    let out = pty.expect("echo");
+    assert!(out.contains("\x1b[32me"), "{:?}", out);
+    pty.send("\x15nosuchcommand");
+    let out = pty.expect("nosuchcommand");
+    assert!(out.contains("\x1b[31mn"), "{:?}", out);
+    pty.send("\x15exit\r");
+    assert_eq!(pty.wait(), 0);
+}
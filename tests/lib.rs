@@ -0,0 +1,125 @@
+// The library API used by the embedders, the commands run in this process.
+// Without the test harness which would capture the printed output itself.
+
+use frish::{Builtin, GroupKind, Node, ParseError, Redirect, State, Status, Target};
+use std::io;
+
+fn do_greet(state: &State, args: &[&str]) -> io::Result<Status> {
+    println!("hello {} from {}", args[1..].join(" "), state.name());
+    Ok(Status::from_code(args.len() as i32 - 1))
+}
+
+fn parse() {
+    let cmd = frish::parse("cmd a \"b c\" <in >out 2>&1 &").unwrap();
+    assert_eq!(cmd.args, ["cmd", "a", "b c"]);
+    let redirect = |fd, target| Redirect { fd, target };
+    assert_eq!(
+        cmd.redirects,
+        [
+            redirect(0, Target::Read("in")),
+            redirect(1, Target::Write("out")),
+            redirect(2, Target::Dup(1))
+        ]
+    );
+    assert!(cmd.background);
+    let cmd = frish::parse("3>> log cmd 4<&- > out x").unwrap();
+    assert_eq!(cmd.args, ["cmd", "x"]);
+    assert_eq!(
+        cmd.redirects,
+        [
+            redirect(3, Target::Append("log")),
+            redirect(4, Target::Close),
+            redirect(1, Target::Write("out"))
+        ]
+    );
+    // nothing after a closing quote is dropped
+    let cmd = frish::parse("echo 'ab'c \"x\"y z").unwrap();
+    assert_eq!(cmd.args, ["echo", "ab", "c", "x", "y", "z"]);
+    assert!(frish::parse("   ").is_none());
+    assert!(frish::parse(">out").is_none());
+}
+
+fn parse_list() {
+    let list = frish::parse_list("a; ( b\n{ c; } ) >out &\nd").unwrap();
+    assert_eq!(list.len(), 3);
+    match &list[1] {
+        Node::Group(group) => {
+            assert_eq!(group.kind, GroupKind::Subshell);
+            assert_eq!(group.list.len(), 2);
+            assert!(matches!(&group.list[1], Node::Group(inner) if inner.kind == GroupKind::Brace));
+            assert_eq!(group.redirects.len(), 1);
+            assert!(group.background);
+        }
+        node => panic!("not a group: {:?}", node),
+    }
+    assert_eq!(
+        frish::parse_list("{ a; ").unwrap_err(),
+        ParseError::Incomplete
+    );
+    let err = frish::parse_list("a )").unwrap_err();
+    assert_eq!(err, ParseError::Unexpected(String::from(")")));
+}
+
+fn eval_status() {
+    let state = State::new("lib", false);
+    assert_eq!(frish::eval(&state, "name other").code(), 0);
+    assert_eq!(state.name(), "other");
+    let out = frish::eval_captured(&state, "file.stat /nonexistent").unwrap();
+    assert_eq!(out.status.code(), 2);
+    assert!(out.stderr.starts_with(b"Error"));
+}
+
+fn captured_output() {
+    let state = State::new("lib", false);
+    let out = frish::eval_captured(&state, "echo one\nprint two\nsh -c \"echo three\"").unwrap();
+    assert_eq!(out.stdout, b"one\ntwothree\n");
+    assert_eq!(out.stderr, b"");
+    assert_eq!(out.status, Status::success());
+}
+
+fn registered_builtin() {
+    let state = State::new("lib", false);
+    let old = state.register_builtin(Builtin::new("greet", do_greet, "Greet"));
+    assert!(old.is_none());
+    assert!(state.has_builtin("greet"));
+    let out = frish::eval_captured(&state, "greet a b\nhelp").unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("hello a b from lib\n"), "{}", stdout);
+    assert!(stdout.contains("greet           Greet\n"), "{}", stdout);
+    // replace a default builtin
+    let old = state.register_builtin(Builtin::new("echo", do_greet, "Greet"));
+    assert_eq!(old.map(|builtin| builtin.command), Some("echo"));
+    let out = frish::eval_captured(&state, "echo x").unwrap();
+    assert_eq!(out.stdout, b"hello x from lib\n");
+    assert_eq!(out.status.code(), 1);
+    let old = state.unregister_builtin("greet");
+    assert_eq!(old.map(|builtin| builtin.command), Some("greet"));
+    assert!(!state.has_builtin("greet"));
+    assert!(state.unregister_builtin("greet").is_none());
+}
+
+fn eval_file() {
+    let path = std::env::temp_dir().join(format!("frish-lib-{}", std::process::id()));
+    std::fs::write(&path, "name script\nexit 5\nname not-reached\n").unwrap();
+    let state = State::new("lib", false);
+    let status = frish::eval_file(&state, &path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(status.code(), 5);
+    assert_eq!(state.name(), "script");
+    assert!(!state.is_running());
+}
+
+fn main() {
+    let tests: &[(&str, fn())] = &[
+        ("parse", parse),
+        ("parse_list", parse_list),
+        ("eval_status", eval_status),
+        ("captured_output", captured_output),
+        ("registered_builtin", registered_builtin),
+        ("eval_file", eval_file),
+    ];
+    for (name, test) in tests {
+        test();
+        eprintln!("test {} ... ok", name);
+    }
+}
@@ -0,0 +1,435 @@
+mod common;
+
+use common::{run, run_args, run_env, run_in, wait_for_file, TempDir};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+// directories a and b on PATH, each one with the script `tool` printing its directory
+fn tools(dir: &TempDir) -> String {
+    for name in ["a", "b"] {
+        fs::create_dir(dir.join(name)).unwrap();
+        let tool = dir.write(
+            &format!("{}/tool", name),
+            &format!("#!/bin/sh\necho {}\n", name),
+        );
+        fs::set_permissions(tool, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    format!("{}:{}:/usr/bin:/bin", a.display(), b.display())
+}
+
+#[test]
+fn pid_and_ppid() {
+    let mut child = common::frish();
+    let out = child.arg("-c").arg("pid\nppid").output().unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let numbers: Vec<u32> = stdout.lines().map(|line| line.parse().unwrap()).collect();
+    assert_eq!(numbers.len(), 2);
+    assert_eq!(numbers[1], std::process::id());
+    assert_ne!(numbers[0], numbers[1]);
+}
+
+#[test]
+fn status_and_exit() {
+    let out = run("status\nfile.stat /nonexistent\nstatus\nstatus\nexit 3\necho not reached\n");
+    assert_eq!(out.stdout, "0\n2\n0\n");
+    assert_eq!(out.code, 3);
+    let out = run("exit\n");
+    assert_eq!(out.code, 0);
+}
+
+#[test]
+fn status_of_external() {
+    let out = run("false\nstatus\ntrue\nstatus\nsh -c \"exit 5\"\nstatus\n");
+    assert_eq!(out.stdout, "1\n0\n5\n");
+}
+
+#[test]
+fn external_command() {
+    let out = run("printf %s-%s a \"b c\"\nnonexistent-command\n");
+    assert_eq!(out.stdout, "a-b c");
+    assert!(!out.stderr.is_empty());
+}
+
+#[test]
+fn depth_and_subshell() {
+    let out = run("depth\nsubshell depth\nsubshell \"subshell depth\"\ndepth\n");
+    assert_eq!(out.stdout, "0\n1\n2\n0\n");
+}
+
+#[test]
+fn subshell_status_and_exit() {
+    let out = run("subshell \"exit 4\"\nstatus\necho still running\n");
+    assert_eq!(out.stdout, "4\nstill running\n");
+    assert_eq!(out.code, 0);
+}
+
+#[test]
+fn subshell_runs_in_child() {
+    let dir = TempDir::new();
+    let script = "subshell \"dir.change /; umask 077\"\ndir.where\numask\n";
+    let out = run_in(dir.path(), script);
+    assert_eq!(out.stdout, format!("{}\n0022\n", dir.path().display()));
+}
+
+#[test]
+fn command_lists() {
+    let out = run("echo a; echo b;echo c\necho \"d;e\" ';'\n");
+    assert_eq!(out.stdout, "a\nb\nc\nd;e ;\n");
+    let out = run("echo a; exit 4; echo b\n");
+    assert_eq!(out.stdout, "a\n");
+    assert_eq!(out.code, 4);
+}
+
+#[test]
+fn subshell_groups() {
+    let dir = TempDir::new();
+    let script = "(dir.change /; dir.where; depth; (depth); exit 3); status\ndir.where\n\
+                  ( echo one\n  echo two ) >out; file.head out\n( echo bg >bg ) &\n";
+    let out = run_in(dir.path(), script);
+    let expected = format!("/\n1\n2\n3\n{}\none\ntwo\n", dir.path().display());
+    assert_eq!(out.stdout, expected);
+    assert_eq!(wait_for_file(&dir.join("bg")), "bg\n");
+}
+
+#[test]
+fn brace_groups() {
+    let dir = TempDir::new();
+    let script = "{ echo a; ls nosuch; } >out 2>&1\nfile.head -n 1 out\n\
+                  { dir.change /; depth; }; dir.where\n{ exit 5; }; echo not reached\n";
+    let out = run_in(dir.path(), script);
+    assert_eq!(out.stdout, "a\n0\n/\n");
+    assert!(dir.read("out").contains("nosuch"));
+    assert_eq!(out.code, 5);
+}
+
+#[test]
+fn syntax_errors() {
+    let out = run("echo a )\nstatus\n}\n( echo b\n");
+    assert_eq!(out.stdout, "2\n");
+    assert_eq!(
+        out.stderr,
+        "frish: syntax error near unexpected token ')'\n\
+         frish: syntax error near unexpected token '}'\n\
+         frish: syntax error: unexpected end of input\n"
+    );
+}
+
+#[test]
+fn subshell_reads_commands() {
+    let out = run("subshell\ndepth\nexit\ndepth\n");
+    assert_eq!(out.stdout, "1\n0\n");
+}
+
+#[test]
+fn pipes() {
+    let out =
+        run("pipes \"echo one two\" \"file.count -w\"\npipes \"seq 3\" tac \"file.head -n 1\"\n");
+    assert_eq!(out.stdout, "2\n3\n");
+    let out = run("pipes\nstatus\npipes \"echo a\"\nstatus\n");
+    assert_eq!(out.stdout, "2\n2\n");
+    assert_eq!(
+        out.stderr.matches("at least two commands expected").count(),
+        2
+    );
+}
+
+#[test]
+fn pipes_of_builtins_and_externals() {
+    let dir = TempDir::new();
+    dir.write("f", "3\n1\n2\n");
+    let out = run_in(dir.path(), "pipes \"cpcat f -\" sort \"file.head -n 2\"\n");
+    assert_eq!(out.stdout, "1\n2\n");
+}
+
+#[test]
+fn redirections() {
+    let dir = TempDir::new();
+    dir.write("in", "b\na\n");
+    let out = run_in(
+        dir.path(),
+        "echo hello >out\nsort <in >sorted\nfile.count -l <in\ncpcat out -\nsort <nonexistent\n",
+    );
+    assert_eq!(dir.read("out"), "hello\n");
+    assert_eq!(dir.read("sorted"), "a\nb\n");
+    assert_eq!(out.stdout, "2\nhello\n");
+    assert!(!out.stderr.is_empty());
+}
+
+#[test]
+fn redirection_truncates() {
+    let dir = TempDir::new();
+    dir.write("out", "a long line of text\n");
+    run_in(dir.path(), "echo short >out\n");
+    assert_eq!(dir.read("out"), "short\n");
+}
+
+#[test]
+fn descriptor_redirections() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "echo a > log\necho b >>log\nls nosuch 2>err\nsh -c \"echo c; echo d >&2\" >>log 2>&1\n\
+         echo e >&3\necho f 2>&1 >&12\n",
+    );
+    assert_eq!(dir.read("log"), "a\nb\nc\nd\n");
+    assert!(dir.read("err").contains("nosuch"));
+    assert_eq!(
+        out.stderr,
+        "Error: 3: Bad file descriptor\nError: 12: Bad file descriptor\n"
+    );
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn exec_redirections() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "exec 3>fd3\necho a >&3\nsh -c \"echo b >&3\"\nexec 3>&-\necho c >&3\n\
+         exec >out 2>&1\necho d\nls nosuch\n",
+    );
+    assert_eq!(dir.read("fd3"), "a\nb\n");
+    assert_eq!(out.stderr, "Error: 3: Bad file descriptor\n");
+    let text = dir.read("out");
+    assert!(
+        text.starts_with("d\n") && text.contains("nosuch"),
+        "{}",
+        text
+    );
+    assert_eq!(out.stdout, "");
+}
+
+#[test]
+fn exec_command() {
+    let out = run("exec sh -c \"echo replaced; exit 6\"\necho not reached\n");
+    assert_eq!(out.stdout, "replaced\n");
+    assert_eq!(out.code, 6);
+    let out = run("exec nosuch\necho not reached\n");
+    assert_eq!(out.stdout, "");
+    assert_eq!(out.stderr, "frish: nosuch: command not found\n");
+    assert_eq!(out.code, 127);
+}
+
+#[test]
+fn background_jobs() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "lastpid\nsh -c \"sleep 0.2; echo done >bg\" &\nlastpid\necho first\n",
+    );
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines[0], "0");
+    assert_ne!(lines[1].parse::<u32>().unwrap(), 0);
+    assert_eq!(lines[2], "first");
+    // the job finishes after the shell
+    assert_eq!(wait_for_file(&dir.join("bg")), "done\n");
+}
+
+#[test]
+fn background_builtin() {
+    let dir = TempDir::new();
+    run_in(dir.path(), "echo async >out &\n");
+    assert_eq!(wait_for_file(&dir.join("out")), "async\n");
+}
+
+#[test]
+fn type_and_which() {
+    let dir = TempDir::new();
+    let path = tools(&dir);
+    let a = dir.join("a/tool");
+    let b = dir.join("b/tool");
+    let out = run_env(
+        &[("PATH", &path)],
+        "type tool echo nosuch\ntype -t echo tool\n",
+    );
+    let expected = format!(
+        "tool is {}\necho is a shell builtin\nbuiltin\nfile\n",
+        a.display()
+    );
+    assert_eq!(out.stdout, expected);
+    assert!(out.stderr.contains("nosuch: not found"), "{}", out.stderr);
+    let out = run_env(
+        &[("PATH", &path)],
+        "which tool\nwhich -a tool\nwhich nosuch\n",
+    );
+    let expected = format!("{0}\n{0}\n{1}\n", a.display(), b.display());
+    assert_eq!(out.stdout, expected);
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn hash_table() {
+    let dir = TempDir::new();
+    let path = tools(&dir);
+    let a = dir.join("a/tool");
+    let script =
+        "hash\ntool\ntool\nhash\ntype tool\nhash -r\nhash\nhash tool nosuch\nhash -t tool\n";
+    let out = run_env(&[("PATH", &path)], script);
+    let expected = format!(
+        "a\na\nhits\tcommand\n   2\t{0}\ntool is hashed ({0})\n{0}\n",
+        a.display()
+    );
+    assert_eq!(out.stdout, expected);
+    assert!(out.stderr.contains("nosuch: not found"), "{}", out.stderr);
+    // a hashed file which is gone is searched for again
+    let script = format!("tool\nunlink {}\ntool\n", a.display());
+    let out = run_env(&[("PATH", &path)], &script);
+    assert_eq!(out.stdout, "a\nb\n");
+}
+
+#[test]
+fn command_and_builtin() {
+    let dir = TempDir::new();
+    let path = tools(&dir);
+    let script = "command -v echo\ncommand -v tool\ncommand tool\ndisable echo\n\
+                  builtin echo -E x\ncommand echo -E y\nbuiltin tool\n";
+    let out = run_env(&[("PATH", &path)], script);
+    let expected = format!("echo\n{}\na\n-E x\ny\n", dir.join("a/tool").display());
+    assert_eq!(out.stdout, expected);
+    assert!(
+        out.stderr.contains("tool: not a shell builtin"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(out.code, 1);
+}
+
+#[test]
+fn exec_failures() {
+    let dir = TempDir::new();
+    let script = |name: &str, content: &str, mode: u32| {
+        let path = dir.write(name, content);
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    };
+    script("bad", "#!/nonexistent/sh\necho hi\n", 0o755);
+    script("plain", "echo from frish\nexit 4\n", 0o755);
+    script("noexec", "#!/bin/sh\n", 0o644);
+    fs::create_dir(dir.join("sub")).unwrap();
+    let out = run_in(
+        dir.path(),
+        "nosuch\nstatus\n./bad\nstatus\n./plain\nstatus\n./noexec\nstatus\n\
+         ./sub\nstatus\n./missing\nstatus\n",
+    );
+    assert_eq!(out.stdout, "127\n126\nfrom frish\n4\n126\n126\n127\n");
+    assert_eq!(
+        out.stderr,
+        "frish: nosuch: command not found\n\
+         frish: ./bad: /nonexistent/sh: bad interpreter: No such file or directory\n\
+         frish: ./noexec: permission denied\n\
+         frish: ./sub: is a directory\n\
+         frish: ./missing: No such file or directory\n"
+    );
+    let out = run("ls a\0b\nstatus\n");
+    assert_eq!(out.stdout, "126\n");
+}
+
+#[test]
+fn traps() {
+    let script = "trap \"echo failed\" ERR\ntrap \"echo bye\" EXIT\ntrap \"echo usr1\" USR1 2\n\
+                  trap\nfalse\nstatus\nsh -c 'kill -USR1 $PPID'\necho after\n\
+                  trap - USR1 ERR\ntrap -p EXIT USR1\nfalse\ntrap x NOSIG\nexit 3\n";
+    let out = run(script);
+    assert_eq!(
+        out.stdout,
+        "trap -- 'echo bye' EXIT\ntrap -- 'echo usr1' INT\ntrap -- 'echo usr1' USR1\n\
+         trap -- 'echo failed' ERR\nfailed\n1\nusr1\nafter\ntrap -- 'echo bye' EXIT\nbye\n"
+    );
+    assert_eq!(out.stderr, "Error: NOSIG: invalid signal specification\n");
+    assert_eq!(out.code, 3);
+}
+
+#[test]
+fn trap_print_reads_back() {
+    let script = "trap 'echo \"a b\"' INT\ntrap \"echo it's\" TERM\ntrap -p >traps\n";
+    let dir = TempDir::new();
+    run_in(dir.path(), script);
+    let traps = dir.read("traps");
+    assert_eq!(
+        traps,
+        "trap -- 'echo \"a b\"' INT\ntrap -- \"echo it's\" TERM\n"
+    );
+    let out = run(&format!("{}trap -p\n", traps));
+    assert_eq!(out.stdout, traps);
+}
+
+#[test]
+fn err_trap_in_groups() {
+    let script = "trap \"echo ERR\" ERR\n{ file.stat /nonexistent; }\n{ { false; }; }\n\
+                  { true; } >/nonexistent/out\n(false)\n";
+    let out = run(script);
+    assert_eq!(out.stdout, "ERR\nERR\nERR\nERR\n");
+}
+
+#[test]
+fn trap_exit_and_ignore() {
+    let out = run("trap \"echo bye; exit 5\" EXIT\necho a\n");
+    assert_eq!(out.stdout, "a\nbye\n");
+    assert_eq!(out.code, 5);
+    let out = run("trap \"\" TERM\nsh -c 'kill $PPID'\necho alive\n");
+    assert_eq!(out.stdout, "alive\n");
+    let out = run("trap \"echo term; exit 4\" TERM\nsh -c 'kill $PPID'\necho not reached\n");
+    assert_eq!(out.stdout, "term\n");
+    assert_eq!(out.code, 4);
+    // a subshell has its own traps
+    let out = run("trap \"echo parent\" EXIT\n(trap \"echo child\" EXIT; echo sub)\necho main\n");
+    assert_eq!(out.stdout, "sub\nchild\nmain\nparent\n");
+}
+
+#[test]
+fn errexit() {
+    let out = run_args(&["-e"], "echo a\nfalse\necho not reached\n");
+    assert_eq!(out.stdout, "a\n");
+    assert_eq!(out.code, 1);
+    let script = "trap \"echo err\" ERR\nset -o errexit\n{ true; }\n(exit 3)\necho not reached\n";
+    let out = run(script);
+    assert_eq!(out.stdout, "err\n");
+    assert_eq!(out.code, 3);
+    let out = run("set -e\nset +e\nfalse\necho still running\n");
+    assert_eq!(out.stdout, "still running\n");
+}
+
+#[test]
+fn xtrace() {
+    let out = run("set -x\necho a  b\n{ echo c; }\nset +x\necho d\n");
+    assert_eq!(out.stdout, "a b\nc\nd\n");
+    assert_eq!(out.stderr, "+ echo a b\n+ echo c\n+ set +x\n");
+    let out = run_env(&[("PS4", ">> ")], "set -x\necho a\n");
+    assert_eq!(out.stderr, ">> echo a\n");
+}
+
+#[test]
+fn pipefail() {
+    let script = "pipes 'sh -c \"exit 3\"' true\nstatus\npipes true 'sh -c \"exit 4\"'\nstatus\n\
+                  set -o pipefail\npipes 'sh -c \"exit 3\"' 'sh -c \"exit 5\"' true\nstatus\n\
+                  pipes 'echo a' 'file.count -l'\nstatus\n";
+    let out = run(script);
+    assert_eq!(out.stdout, "0\n4\n5\n1\n0\n");
+}
+
+#[test]
+fn noexec() {
+    let out = run_args(&["-n"], "echo a\nset +n\necho b\n");
+    assert_eq!(out.stdout, "");
+    assert_eq!(out.code, 0);
+    let out = run_args(&["-n"], "echo a )\n");
+    assert_eq!(
+        out.stderr,
+        "frish: syntax error near unexpected token ')'\n"
+    );
+    assert_eq!(out.code, 2);
+}
+
+#[test]
+fn noclobber() {
+    let dir = TempDir::new();
+    let out = run_in(
+        dir.path(),
+        "echo a >out\nset -C\necho b >out\nstatus\necho c >>out\necho d >new\n\
+         echo e >/dev/null\necho f >|new\nset +C\necho g >out\n",
+    );
+    assert_eq!(out.stdout, "1\n");
+    assert_eq!(out.stderr, "Error: out: cannot overwrite existing file\n");
+    assert_eq!(dir.read("out"), "g\n");
+    assert_eq!(dir.read("new"), "f\n");
+}